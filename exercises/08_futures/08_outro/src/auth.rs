@@ -0,0 +1,215 @@
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use ticket_fields::TicketAssignee;
+use tide::{Middleware, Next, Request};
+use crate::data;
+use crate::policy::{Action, Forbidden, Policy, Role};
+use crate::server::{rfc3339, MyError};
+
+/// The file tokens are kept in, within the storage directory.
+pub const TOKENS_FILE: &str = "tokens.json";
+
+/// Every token starts with it, so they are easy to spot, e.g. by secret scanners.
+const TOKEN_PREFIX: &str = "tkt_";
+
+/// Random bytes in a token.
+const TOKEN_BYTES: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct TokenId(pub u64);
+
+/// A token, as it is stored: only its hash is kept, the token itself is
+/// only known when it is issued.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenInfo {
+    pub id: TokenId,
+    /// Users are named like assignees, so tickets can be assigned to them.
+    pub user: String,
+    #[serde(with = "rfc3339")]
+    pub issued_at: SystemTime,
+    /// The SHA-256 of the token, in hexadecimal.
+    hash: String,
+}
+
+/// The user a request was authenticated as, attached to it by `Authenticate`.
+#[derive(Clone, Debug)]
+pub struct User {
+    pub name: TicketAssignee,
+    /// The token the request was authenticated with.
+    pub token: TokenId,
+    policy: Arc<Policy>,
+}
+
+impl User {
+    pub fn role(&self) -> Role {
+        self.policy.role(&self.name.0)
+    }
+
+    pub fn authorize(&self, action: Action) -> Result<(), Forbidden> {
+        self.policy.authorize(&self.name.0, action)
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct TokenFile {
+    next_id: u64,
+    tokens: Vec<TokenInfo>,
+}
+
+#[derive(Debug, Default)]
+struct Loaded {
+    file: TokenFile,
+    /// When the file was modified, and its length, as of the last time it was read.
+    modified: Option<(SystemTime, u64)>,
+}
+
+/// The API tokens users authenticate with.
+///
+/// Tokens are kept in a JSON file, which is read again whenever it changes:
+/// tokens issued or revoked by another process, like the `tokens` binary,
+/// are picked up by a running server.
+#[derive(Debug, Default)]
+pub struct Tokens {
+    path: Option<PathBuf>,
+    loaded: Mutex<Loaded>,
+}
+
+impl Tokens {
+    /// Tokens that are not persisted, and gone once dropped.
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    /// The tokens persisted in `dir`, which is created if it doesn't exist.
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let tokens = Tokens { path: Some(dir.join(TOKENS_FILE)), loaded: Mutex::default() };
+        tokens.refresh(&mut tokens.loaded.lock().unwrap())?;
+        Ok(tokens)
+    }
+
+    /// Issues a new token for `user`. The returned token is the only way to get it back.
+    pub fn issue(&self, user: &TicketAssignee) -> io::Result<(TokenInfo, String)> {
+        let mut bytes = [0; TOKEN_BYTES];
+        getrandom::getrandom(&mut bytes).map_err(io::Error::other)?;
+        let mut token = TOKEN_PREFIX.to_string();
+        for byte in bytes {
+            write!(token, "{:02x}", byte).expect("writing to a string cannot fail");
+        }
+
+        let mut loaded = self.loaded.lock().unwrap();
+        self.refresh(&mut loaded)?;
+        let info = TokenInfo {
+            id: TokenId(loaded.file.next_id),
+            user: user.0.clone(),
+            issued_at: data::now(),
+            hash: hash(&token),
+        };
+        loaded.file.next_id += 1;
+        loaded.file.tokens.push(info.clone());
+        self.save(&mut loaded)?;
+        Ok((info, token))
+    }
+
+    /// Returns whether the token existed.
+    pub fn revoke(&self, id: TokenId) -> io::Result<bool> {
+        let mut loaded = self.loaded.lock().unwrap();
+        self.refresh(&mut loaded)?;
+        let count = loaded.file.tokens.len();
+        loaded.file.tokens.retain(|info| info.id != id);
+        if loaded.file.tokens.len() == count {
+            return Ok(false);
+        }
+        self.save(&mut loaded)?;
+        Ok(true)
+    }
+
+    /// The tokens that have not been revoked, in the order they were issued.
+    pub fn list(&self) -> io::Result<Vec<TokenInfo>> {
+        let mut loaded = self.loaded.lock().unwrap();
+        self.refresh(&mut loaded)?;
+        Ok(loaded.file.tokens.clone())
+    }
+
+    /// Finds `token`, unless it is unknown or revoked.
+    pub fn authenticate(&self, token: &str) -> io::Result<Option<TokenInfo>> {
+        let mut loaded = self.loaded.lock().unwrap();
+        self.refresh(&mut loaded)?;
+        let hash = hash(token);
+        Ok(loaded.file.tokens.iter().find(|info| info.hash == hash).cloned())
+    }
+
+    /// Reads the file again if it changed since it was last read.
+    fn refresh(&self, loaded: &mut Loaded) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let modified = match fs::metadata(path) {
+            Ok(metadata) => (metadata.modified()?, metadata.len()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                *loaded = Loaded::default();
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        if loaded.modified != Some(modified) {
+            let content = fs::read(path)?;
+            loaded.file = serde_json::from_slice(&content)?;
+            loaded.modified = Some(modified);
+        }
+        Ok(())
+    }
+
+    fn save(&self, loaded: &mut Loaded) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let tmp_path = path.with_extension("json.tmp");
+        let mut tmp = File::create(&tmp_path)?;
+        serde_json::to_writer_pretty(&mut tmp, &loaded.file)?;
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, path)?;
+        let metadata = fs::metadata(path)?;
+        loaded.modified = Some((metadata.modified()?, metadata.len()));
+        Ok(())
+    }
+}
+
+fn hash(token: &str) -> String {
+    let digest = Sha256::digest(token.as_bytes());
+    let mut hex = String::with_capacity(2 * digest.len());
+    for byte in digest {
+        write!(hex, "{:02x}", byte).expect("writing to a string cannot fail");
+    }
+    hex
+}
+
+/// Rejects requests without a valid `Authorization: Bearer` token,
+/// and attaches the `User` the token was issued for to the others,
+/// with the permissions `policy` gives them.
+#[derive(Clone, Debug)]
+pub struct Authenticate {
+    pub tokens: Arc<Tokens>,
+    pub policy: Arc<Policy>,
+}
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for Authenticate {
+    async fn handle(&self, mut req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let token = req.header("Authorization")
+            .and_then(|value| value.as_str().strip_prefix("Bearer "))
+            .map(str::trim)
+            .ok_or_else(|| MyError::Unauthorized("Missing bearer token".to_string()))?;
+        let info = self.tokens.authenticate(token)
+            .map_err(|e| MyError::Internal(e.to_string()))?
+            .ok_or_else(|| MyError::Unauthorized("Invalid or revoked token".to_string()))?;
+        req.set_ext(User { name: TicketAssignee(info.user), token: info.id, policy: self.policy.clone() });
+        Ok(next.run(req).await)
+    }
+}
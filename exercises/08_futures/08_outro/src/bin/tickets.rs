@@ -0,0 +1,460 @@
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::Duration;
+use anyhow::{bail, Context};
+use async_std::io::BufReader;
+use clap::{Parser, Subcommand, ValueEnum};
+use outro_08::bulk::BulkFormat;
+use outro_08::client::TicketClient;
+use outro_08::data::{Comment, Priority, Status, Ticket, TicketDraft, TicketFilter, TicketPatch};
+use outro_08::history::HistoryEntry;
+use outro_08::links::Relation;
+use outro_08::server::{GetCommentResponse, GetTicketResponse};
+use outro_08::store::{CommentId, TicketId};
+use surf::Body;
+use ticket_fields::{TicketAssignee, TicketDescription, TicketLabel};
+
+/// Manage tickets on a running ticket server.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Cli {
+    /// Base URL of the ticket server.
+    #[arg(long, env = "TICKETS_SERVER", default_value = "http://127.0.0.1:8080/")]
+    server: surf::Url,
+
+    /// API token, if the server requires one.
+    #[arg(long, env = "TICKETS_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+
+    #[arg(long, value_enum, default_value_t = Output::Table)]
+    output: Output,
+
+    #[command(subcommand)]
+    command: Cmd,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Output {
+    Table,
+    Json,
+}
+
+#[derive(Subcommand, Debug)]
+enum Cmd {
+    /// Create a ticket. The description is read from `$EDITOR` unless given.
+    Create {
+        #[arg(long)]
+        title: String,
+        /// Use `-` to read it from stdin.
+        #[arg(long)]
+        description: Option<String>,
+        #[arg(long, default_value_t = Priority::default())]
+        priority: Priority,
+        #[arg(long)]
+        assignee: Option<String>,
+        /// Can be repeated.
+        #[arg(long = "label")]
+        labels: Vec<String>,
+    },
+    /// Show a single ticket.
+    Show { id: u64 },
+    /// List tickets, in id order.
+    List {
+        #[arg(long)]
+        status: Option<Status>,
+        #[arg(long)]
+        priority: Option<Priority>,
+        #[arg(long)]
+        assignee: Option<String>,
+        #[arg(long)]
+        label: Option<String>,
+        #[arg(long)]
+        after: Option<u64>,
+        #[arg(long)]
+        limit: Option<usize>,
+    },
+    /// Change some fields of a ticket.
+    Update {
+        id: u64,
+        #[arg(long)]
+        title: Option<String>,
+        /// Use `-` to read it from stdin, or `@edit` to edit the current one in `$EDITOR`.
+        #[arg(long)]
+        description: Option<String>,
+        #[arg(long)]
+        status: Option<Status>,
+        #[arg(long)]
+        priority: Option<Priority>,
+        #[arg(long, conflicts_with = "unassign")]
+        assignee: Option<String>,
+        #[arg(long)]
+        unassign: bool,
+        /// Replaces all the labels. Can be repeated.
+        #[arg(long = "label", conflicts_with = "clear_labels")]
+        labels: Vec<String>,
+        #[arg(long)]
+        clear_labels: bool,
+        /// Only update if the ticket is still at this version.
+        #[arg(long)]
+        if_version: Option<u64>,
+    },
+    /// Delete a ticket.
+    Delete {
+        id: u64,
+        /// Only delete if the ticket is still at this version.
+        #[arg(long)]
+        if_version: Option<u64>,
+    },
+    /// Link a ticket to another one, e.g. `link 1 blocked-by 2`.
+    Link { id: u64, relation: Relation, other: u64 },
+    /// Remove a link between two tickets.
+    Unlink { id: u64, relation: Relation, other: u64 },
+    /// List the links of a ticket, and the tickets blocking it directly or not.
+    Links { id: u64 },
+    /// Comment on a ticket. The comment is read from `$EDITOR` unless given.
+    Comment {
+        id: u64,
+        /// Defaults to the user the token was issued to.
+        #[arg(long)]
+        author: Option<String>,
+        /// Use `-` to read it from stdin.
+        #[arg(long)]
+        body: Option<String>,
+    },
+    /// List the comments of a ticket, oldest first.
+    Comments {
+        id: u64,
+        #[arg(long)]
+        after: Option<u64>,
+        #[arg(long)]
+        limit: Option<usize>,
+    },
+    /// Show every change applied to a ticket, even a deleted one.
+    History { id: u64 },
+    /// Import tickets from a JSON Lines or CSV file, as written by `export`.
+    Import {
+        /// Use `-` to read from stdin.
+        file: PathBuf,
+        /// Guessed from the file extension if not given.
+        #[arg(long)]
+        format: Option<BulkFormat>,
+        /// Import nothing at all if any row is invalid.
+        #[arg(long)]
+        atomic: bool,
+    },
+    /// Export every ticket as JSON Lines or CSV.
+    Export {
+        #[arg(long, default_value_t = BulkFormat::default())]
+        format: BulkFormat,
+        /// Written to stdout if not given.
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Print a ticket every time it changes, until it is deleted.
+    Watch { id: u64 },
+}
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    let cli = Cli::parse();
+    let mut client = TicketClient::new(cli.server);
+    if let Some(token) = cli.token {
+        client = client.with_token(token);
+    }
+
+    match cli.command {
+        Cmd::Create { title, description, priority, assignee, labels } => {
+            let description = match description {
+                Some(description) => read_argument(description)?,
+                None => edit("")?,
+            };
+            let draft = TicketDraft {
+                title: title.try_into()?,
+                description: description.try_into()?,
+                priority,
+                assignee: assignee.map(TicketAssignee::try_from).transpose()?,
+                labels: labels.into_iter().map(TicketLabel::try_from).collect::<Result<_, _>>()?,
+            };
+            let id = client.create_ticket(draft).await?;
+            match cli.output {
+                Output::Table => println!("Created ticket {}", id.0),
+                Output::Json => println!("{}", serde_json::json!({ "ticket_id": id })),
+            }
+        }
+        Cmd::Show { id } => {
+            let ticket = client.get_ticket(TicketId(id)).await?;
+            print_ticket(&ticket, cli.output)?;
+        }
+        Cmd::List { status, priority, assignee, label, after, limit } => {
+            let filter = TicketFilter {
+                status,
+                priority,
+                assignee: assignee.map(TicketAssignee::try_from).transpose()?,
+                label: label.map(TicketLabel::try_from).transpose()?,
+            };
+            let page = client.list_tickets(&filter, after.map(TicketId), limit).await?;
+            match cli.output {
+                Output::Table => {
+                    print_table(&page.tickets);
+                    if let Some(next) = page.next_after {
+                        println!("(more tickets after {})", next.0);
+                    }
+                }
+                Output::Json => {
+                    let tickets: Vec<_> = page.tickets.into_iter().map(GetTicketResponse).collect();
+                    println!("{}", serde_json::to_string_pretty(&tickets)?);
+                }
+            }
+        }
+        Cmd::Update { id, title, description, status, priority, assignee, unassign, labels, clear_labels, if_version } => {
+            let id = TicketId(id);
+            let description = match description.as_deref() {
+                None => None,
+                Some("@edit") => {
+                    let current = client.get_ticket(id).await?;
+                    Some(edit(&current.description.0)?)
+                }
+                Some(_) => description.map(read_argument).transpose()?,
+            };
+            let patch = TicketPatch {
+                title: title.map(TryInto::try_into).transpose()?,
+                description: description.map(TicketDescription::try_from).transpose()?,
+                status,
+                priority,
+                assignee: match (assignee, unassign) {
+                    (Some(assignee), _) => Some(Some(assignee.try_into()?)),
+                    (None, true) => Some(None),
+                    (None, false) => None,
+                },
+                labels: if clear_labels || !labels.is_empty() {
+                    Some(labels.into_iter().map(TicketLabel::try_from).collect::<Result<_, _>>()?)
+                } else {
+                    None
+                },
+            };
+            let ticket = client.patch_ticket(id, patch, if_version).await?;
+            print_ticket(&ticket, cli.output)?;
+        }
+        Cmd::Delete { id, if_version } => {
+            client.delete_ticket(TicketId(id), if_version).await?;
+            if let Output::Table = cli.output {
+                println!("Deleted ticket {}", id);
+            }
+        }
+        Cmd::Link { id, relation, other } => {
+            client.link_tickets(TicketId(id), relation, TicketId(other)).await?;
+        }
+        Cmd::Unlink { id, relation, other } => {
+            client.unlink_tickets(TicketId(id), relation, TicketId(other)).await?;
+        }
+        Cmd::Links { id } => {
+            let links = client.ticket_links(TicketId(id)).await?;
+            let blockers = client.ticket_blockers(TicketId(id)).await?;
+            match cli.output {
+                Output::Table => {
+                    for link in &links {
+                        println!("{} {}", link.relation, link.ticket_id.0);
+                    }
+                    if !blockers.is_empty() {
+                        let blockers: Vec<_> = blockers.iter().map(|id| id.0.to_string()).collect();
+                        println!("Blocked by: {}", blockers.join(", "));
+                    }
+                }
+                Output::Json => {
+                    let body = serde_json::json!({ "links": links, "blockers": blockers });
+                    println!("{}", serde_json::to_string_pretty(&body)?);
+                }
+            }
+        }
+        Cmd::Comment { id, author, body } => {
+            let body = match body {
+                Some(body) => read_argument(body)?,
+                None => edit("")?,
+            };
+            let author = author.map(TicketAssignee::try_from).transpose()?;
+            let comment = client.add_comment(TicketId(id), author, body.try_into()?).await?;
+            print_comments(&[comment], cli.output)?;
+        }
+        Cmd::Comments { id, after, limit } => {
+            let page = client.list_comments(TicketId(id), after.map(CommentId), limit).await?;
+            print_comments(&page.comments, cli.output)?;
+            if let (Output::Table, Some(next)) = (cli.output, page.next_after) {
+                println!("(more comments after {})", next.0);
+            }
+        }
+        Cmd::History { id } => {
+            let entries = client.ticket_history(TicketId(id)).await?;
+            match cli.output {
+                Output::Table => print_history(&entries),
+                Output::Json => println!("{}", serde_json::to_string_pretty(&entries)?),
+            }
+        }
+        Cmd::Import { file, format, atomic } => {
+            let format = format.unwrap_or(match file.extension() {
+                Some(extension) if extension.eq_ignore_ascii_case("csv") => BulkFormat::Csv,
+                _ => BulkFormat::Jsonl,
+            });
+            let body = if file.as_os_str() == "-" {
+                Body::from_reader(BufReader::new(async_std::io::stdin()), None)
+            } else {
+                let file = async_std::fs::File::open(&file).await
+                    .with_context(|| format!("Failed to open {}", file.display()))?;
+                let length = file.metadata().await?.len() as usize;
+                Body::from_reader(BufReader::new(file), Some(length))
+            };
+            let report = client.import_tickets(format, body, atomic).await?;
+            match cli.output {
+                Output::Table => {
+                    println!("Imported {} tickets", report.imported.len());
+                    for error in &report.errors {
+                        println!("Row {}: {}", error.row, error.error.message);
+                    }
+                }
+                Output::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+            }
+            if !report.errors.is_empty() {
+                bail!("{} rows could not be imported", report.errors.len());
+            }
+        }
+        Cmd::Export { format, output } => {
+            let body = client.export_tickets(format).await?;
+            match output {
+                Some(path) => {
+                    let mut file = async_std::fs::File::create(&path).await
+                        .with_context(|| format!("Failed to create {}", path.display()))?;
+                    async_std::io::copy(body, &mut file).await?;
+                    file.sync_all().await?;
+                }
+                None => {
+                    async_std::io::copy(body, &mut async_std::io::stdout()).await?;
+                }
+            }
+        }
+        Cmd::Watch { id } => {
+            let id = TicketId(id);
+            // Subscribed first, so that no change is missed between the two requests.
+            let mut events = client.ticket_events(None).await?;
+            let ticket = client.get_ticket(id).await?;
+            let mut version = ticket.version;
+            print_ticket(&ticket, cli.output)?;
+            let mut last_seen = None;
+            loop {
+                let Some((event_id, event)) = events.next().await? else {
+                    // The server ended the stream: resume where it stopped.
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    events = client.ticket_events(last_seen).await?;
+                    continue;
+                };
+                last_seen = Some(event_id);
+                if event.ticket_id != id {
+                    continue;
+                }
+                match event.ticket {
+                    Some(GetTicketResponse(ticket)) if ticket.version > version => {
+                        version = ticket.version;
+                        print_ticket(&ticket, cli.output)?;
+                    }
+                    Some(_) => {}
+                    None => {
+                        eprintln!("Ticket {} was deleted", id.0);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns `value`, or what's on stdin if `value` is `-`.
+fn read_argument(value: String) -> io::Result<String> {
+    if value != "-" {
+        return Ok(value);
+    }
+    let mut input = String::new();
+    io::stdin().read_to_string(&mut input)?;
+    Ok(input.trim_end().to_string())
+}
+
+/// Lets the user edit `initial` in `$EDITOR`, and returns the result.
+fn edit(initial: &str) -> Result<String, anyhow::Error> {
+    let editor = std::env::var("EDITOR").context("$EDITOR is not set, pass --description instead")?;
+    // Created with a random name that no one else can have taken, and removed once dropped.
+    let mut file = tempfile::Builder::new().prefix("ticket-").suffix(".txt").tempfile()?;
+    file.write_all(initial.as_bytes())?;
+    file.flush()?;
+    let status = Command::new(&editor).arg(file.path()).status()
+        .with_context(|| format!("Failed to run {}", editor))?;
+    // Read back by path: editors may replace the file instead of writing to it.
+    let content = std::fs::read_to_string(file.path());
+    if !status.success() {
+        bail!("{} exited with {}", editor, status);
+    }
+    Ok(content?.trim_end().to_string())
+}
+
+fn print_ticket(ticket: &Ticket, output: Output) -> Result<(), anyhow::Error> {
+    match output {
+        Output::Table => {
+            println!("Ticket {} (version {})", ticket.id.0, ticket.version);
+            println!("Title:    {}", ticket.title.0);
+            println!("Status:   {}", ticket.status);
+            println!("Priority: {}", ticket.priority);
+            if let Some(assignee) = &ticket.assignee {
+                println!("Assignee: {}", assignee.0);
+            }
+            if !ticket.labels.is_empty() {
+                let labels: Vec<_> = ticket.labels.iter().map(|l| l.0.as_str()).collect();
+                println!("Labels:   {}", labels.join(", "));
+            }
+            println!();
+            println!("{}", ticket.description.0);
+        }
+        Output::Json => {
+            println!("{}", serde_json::to_string_pretty(&GetTicketResponse(ticket.clone()))?);
+        }
+    }
+    Ok(())
+}
+
+fn print_table(tickets: &[Ticket]) {
+    let status_width = tickets.iter()
+        .map(|t| t.status.to_string().len())
+        .chain(["STATUS".len()])
+        .max()
+        .unwrap_or_default();
+    println!("{:>6}  {:<status_width$}  {:<8}  TITLE", "ID", "STATUS", "PRIORITY");
+    for ticket in tickets {
+        let priority = ticket.priority.to_string();
+        println!("{:>6}  {:<status_width$}  {:<8}  {}", ticket.id.0, ticket.status.to_string(), priority, ticket.title.0);
+    }
+}
+
+fn print_comments(comments: &[Comment], output: Output) -> Result<(), anyhow::Error> {
+    match output {
+        Output::Table => {
+            for comment in comments {
+                let timestamp = humantime::format_rfc3339_millis(comment.created_at);
+                println!("#{} by {} at {}", comment.id.0, comment.author.0, timestamp);
+                println!("{}", comment.body.0);
+                println!();
+            }
+        }
+        Output::Json => {
+            let comments: Vec<_> = comments.iter().cloned().map(GetCommentResponse).collect();
+            println!("{}", serde_json::to_string_pretty(&comments)?);
+        }
+    }
+    Ok(())
+}
+
+fn print_history(entries: &[HistoryEntry]) {
+    for entry in entries {
+        let timestamp = humantime::format_rfc3339_millis(entry.timestamp);
+        println!("{}  version {}  {:?}", timestamp, entry.version, entry.action);
+        for change in &entry.changes {
+            println!("    {}: {} -> {}", change.field, change.old, change.new);
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use std::path::PathBuf;
+use anyhow::bail;
+use clap::{Parser, Subcommand};
+use outro_08::auth::{TokenId, Tokens};
+use ticket_fields::TicketAssignee;
+
+/// Issue and revoke the API tokens of a ticket server.
+/// A running server picks up changes without a restart.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Cli {
+    /// Directory where the server persists tickets, and tokens.
+    #[arg(long, env = "TICKETS_STORAGE_PATH", default_value = "tickets-data")]
+    storage_path: PathBuf,
+
+    #[command(subcommand)]
+    command: Cmd,
+}
+
+#[derive(Subcommand, Debug)]
+enum Cmd {
+    /// Issue a token for a user, and print it. It can't be shown again.
+    Issue { user: String },
+    /// Revoke a token, given its id.
+    Revoke { id: u64 },
+    /// List the tokens that have not been revoked.
+    List,
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    let cli = Cli::parse();
+    let tokens = Tokens::open(&cli.storage_path)?;
+
+    match cli.command {
+        Cmd::Issue { user } => {
+            let user = TicketAssignee::try_from(user)?;
+            let (info, token) = tokens.issue(&user)?;
+            eprintln!("Issued token {} for {}", info.id.0, info.user);
+            println!("{}", token);
+        }
+        Cmd::Revoke { id } => {
+            if !tokens.revoke(TokenId(id))? {
+                bail!("No token with id {}", id);
+            }
+        }
+        Cmd::List => {
+            for info in tokens.list()? {
+                let issued_at = humantime::format_rfc3339_seconds(info.issued_at);
+                println!("{:>4}  {:<20}  {}", info.id.0, info.user, issued_at);
+            }
+        }
+    }
+    Ok(())
+}
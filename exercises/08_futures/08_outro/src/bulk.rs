@@ -0,0 +1,360 @@
+use std::convert::TryInto;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use csv::StringRecord;
+use futures::channel::mpsc;
+use futures::io::BufReader;
+use futures::{AsyncBufRead, AsyncBufReadExt, SinkExt, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tide::{Body, Request, Response, StatusCode};
+use utoipa::{IntoParams, ToSchema};
+use crate::data::{Priority, Status, Ticket, TicketDraft, TicketFilter, UnknownPriority, UnknownStatus};
+use crate::policy::Action;
+use crate::repository::TicketRepository;
+use crate::server::{CreateTicketRequest, ErrorBody, ErrorResponse, FieldError, GetTicketResponse, MyError, authorize, store_for};
+use crate::store::{TicketId, TicketStore};
+
+/// How many tickets an export reads at a time, letting writers in between.
+const EXPORT_BATCH_SIZE: usize = 100;
+
+/// The columns of CSV exports, in order. Imports only need `title` and `description`,
+/// in any order, and ignore `id`, `version` and unknown columns.
+pub const CSV_COLUMNS: [&str; 8] = ["id", "title", "description", "status", "priority", "assignee", "labels", "version"];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum BulkFormat {
+    /// JSON Lines: one ticket per line, shaped like the body of `GET /tickets/{id}`.
+    #[default]
+    Jsonl,
+    /// Comma-separated values, with a header row. Labels are separated by spaces.
+    Csv,
+}
+
+impl BulkFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            BulkFormat::Jsonl => "application/x-ndjson",
+            BulkFormat::Csv => "text/csv",
+        }
+    }
+
+    /// A ticket as a complete line, or record, of this format.
+    pub fn encode(self, ticket: Ticket) -> String {
+        match self {
+            BulkFormat::Jsonl => {
+                let mut line = serde_json::to_string(&GetTicketResponse(ticket)).expect("tickets can always be serialized");
+                line.push('\n');
+                line
+            }
+            BulkFormat::Csv => {
+                let labels: Vec<_> = ticket.labels.iter().map(|label| label.0.as_str()).collect();
+                csv_record([
+                    ticket.id.0.to_string(),
+                    ticket.title.0,
+                    ticket.description.0,
+                    ticket.status.to_string(),
+                    ticket.priority.to_string(),
+                    ticket.assignee.map(|assignee| assignee.0).unwrap_or_default(),
+                    labels.join(" "),
+                    ticket.version.to_string(),
+                ])
+            }
+        }
+    }
+}
+
+impl fmt::Display for BulkFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BulkFormat::Jsonl => "jsonl",
+            BulkFormat::Csv => "csv",
+        })
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("Unknown format {0}, expected jsonl or csv")]
+pub struct UnknownFormat(pub String);
+
+impl FromStr for BulkFormat {
+    type Err = UnknownFormat;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [BulkFormat::Jsonl, BulkFormat::Csv].into_iter()
+            .find(|format| format.to_string().eq_ignore_ascii_case(s))
+            .ok_or_else(|| UnknownFormat(s.to_string()))
+    }
+}
+
+fn csv_record<I: AsRef<[u8]>>(fields: impl IntoIterator<Item = I>) -> String {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(fields).expect("writing to memory cannot fail");
+    let bytes = writer.into_inner().expect("writing to memory cannot fail");
+    String::from_utf8(bytes).expect("fields are valid UTF-8")
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImportTicketsQuery {
+    #[serde(default)]
+    #[param(inline)]
+    pub format: BulkFormat,
+    /// Import nothing at all if any row is invalid.
+    #[serde(default)]
+    pub atomic: bool,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportTicketsQuery {
+    #[serde(default)]
+    #[param(inline)]
+    pub format: BulkFormat,
+}
+
+/// A ticket to import, as a JSON Lines row. Ids and versions are given by the server,
+/// so exported tickets can be imported as they are.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ImportTicketRow {
+    pub title: String,
+    pub description: String,
+    /// Defaults to `ToDo`.
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub priority: Option<String>,
+    #[serde(default)]
+    pub assignee: Option<String>,
+    #[serde(default)]
+    pub labels: Vec<String>,
+}
+
+impl TryInto<(TicketDraft, Status)> for ImportTicketRow {
+    type Error = FieldError;
+
+    fn try_into(self) -> Result<(TicketDraft, Status), Self::Error> {
+        let status = match self.status {
+            Some(status) => status.parse().map_err(|e: UnknownStatus| {
+                FieldError { field: "status", code: "status_invalid", message: e.to_string() }
+            })?,
+            None => Status::ToDo,
+        };
+        let priority = match self.priority {
+            Some(priority) => priority.parse().map_err(|e: UnknownPriority| {
+                FieldError { field: "priority", code: "priority_invalid", message: e.to_string() }
+            })?,
+            None => Priority::default(),
+        };
+        let request = CreateTicketRequest {
+            title: self.title,
+            description: self.description,
+            priority,
+            assignee: self.assignee,
+            labels: self.labels,
+        };
+        Ok((request.try_into()?, status))
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct ImportTicketsResponse {
+    /// The ids given to the imported tickets, in row order.
+    pub imported: Vec<TicketId>,
+    /// The rows that were rejected. In atomic mode, nothing is imported if there are any.
+    pub errors: Vec<ImportRowError>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ImportRowError {
+    /// Rows are numbered from 1, not counting blank lines and the CSV header.
+    pub row: usize,
+    pub error: ErrorBody,
+}
+
+impl ImportRowError {
+    fn new(row: usize, error: MyError) -> Self {
+        ImportRowError { row, error: ErrorResponse::from(&error).error }
+    }
+
+    fn malformed(row: usize, message: String) -> Self {
+        ImportRowError { row, error: ErrorBody { code: "invalid_row".to_string(), message, field: None, reason: None } }
+    }
+}
+
+/// Where the fields of imported tickets are in CSV records, from the header.
+struct CsvColumns {
+    title: usize,
+    description: usize,
+    status: Option<usize>,
+    priority: Option<usize>,
+    assignee: Option<usize>,
+    labels: Option<usize>,
+}
+
+impl CsvColumns {
+    fn new(header: &StringRecord) -> Result<Self, MyError> {
+        let find = |name: &str| header.iter().position(|column| column.trim().eq_ignore_ascii_case(name));
+        let required = |name: &str| find(name).ok_or_else(|| MyError::BadRequest {
+            field: "body",
+            message: format!("The CSV header has no {} column", name),
+        });
+        Ok(CsvColumns {
+            title: required("title")?,
+            description: required("description")?,
+            status: find("status"),
+            priority: find("priority"),
+            assignee: find("assignee"),
+            labels: find("labels"),
+        })
+    }
+
+    /// Empty optional fields are taken as missing.
+    fn row(&self, record: &StringRecord) -> ImportTicketRow {
+        let optional = |column: Option<usize>| column
+            .and_then(|i| record.get(i))
+            .filter(|value| !value.is_empty())
+            .map(str::to_string);
+        ImportTicketRow {
+            title: record.get(self.title).unwrap_or_default().to_string(),
+            description: record.get(self.description).unwrap_or_default().to_string(),
+            status: optional(self.status),
+            priority: optional(self.priority),
+            assignee: optional(self.assignee),
+            labels: optional(self.labels)
+                .map(|labels| labels.split_whitespace().map(str::to_string).collect())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+fn parse_csv_record(text: &str) -> Result<StringRecord, csv::Error> {
+    let mut reader = csv::ReaderBuilder::new().has_headers(false).flexible(true).from_reader(text.as_bytes());
+    reader.records().next().unwrap_or_else(|| Ok(StringRecord::new()))
+}
+
+/// The valid rows of an import, and the errors of the others.
+#[derive(Default)]
+struct ImportRows {
+    tickets: Vec<(TicketDraft, Status)>,
+    errors: Vec<ImportRowError>,
+}
+
+/// Validates rows as they are read from `body`, so the raw import is never held in memory.
+async fn read_rows(body: impl AsyncBufRead + Unpin, format: BulkFormat) -> Result<ImportRows, MyError> {
+    let mut rows = ImportRows::default();
+    let mut columns = None;
+    let mut row = 0;
+    let mut lines = body.lines();
+    // A quoted CSV field can span several lines.
+    let mut record = String::new();
+    while let Some(line) = lines.next().await {
+        let line = line.map_err(|e| MyError::BadRequest { field: "body", message: e.to_string() })?;
+        if !record.is_empty() {
+            record.push('\n');
+        }
+        record.push_str(&line);
+        if format == BulkFormat::Csv && record.matches('"').count() % 2 == 1 {
+            continue;
+        }
+        let text = std::mem::take(&mut record);
+        if text.trim().is_empty() {
+            continue;
+        }
+
+        let parsed = match format {
+            BulkFormat::Jsonl => serde_json::from_str::<ImportTicketRow>(&text).map_err(|e| e.to_string()),
+            BulkFormat::Csv => match (parse_csv_record(&text), &columns) {
+                (Ok(header), None) => {
+                    columns = Some(CsvColumns::new(&header)?);
+                    continue;
+                }
+                (Ok(record), Some(columns)) => Ok(columns.row(&record)),
+                (Err(e), _) => Err(e.to_string()),
+            },
+        };
+        row += 1;
+        match parsed.map(|parsed| parsed.try_into()) {
+            Ok(Ok(ticket)) => rows.tickets.push(ticket),
+            Ok(Err(e)) => rows.errors.push(ImportRowError::new(row, MyError::from(e))),
+            Err(message) => rows.errors.push(ImportRowError::malformed(row, message)),
+        }
+    }
+    if !record.is_empty() {
+        rows.errors.push(ImportRowError::malformed(row + 1, "Unterminated quoted field".to_string()));
+    }
+    Ok(rows)
+}
+
+#[utoipa::path(
+    post, path = "/tickets/import", params(ImportTicketsQuery),
+    request_body(content = String, description = "Tickets in the given format, read as they arrive",
+        content_type = "application/x-ndjson"),
+    responses(
+        (status = 200, body = ImportTicketsResponse, description = "Which rows were imported, and why the others were not"),
+        (status = 400, body = ErrorResponse),
+    ),
+)]
+pub async fn import_tickets<R: TicketRepository>(mut req: Request<TicketStore<R>>) -> tide::Result {
+    authorize(&req, Action::Import)?;
+    let query: ImportTicketsQuery = req.query()
+        .map_err(|e| MyError::BadRequest { field: "query", message: e.to_string() })?;
+    let rows = read_rows(req.take_body(), query.format).await?;
+
+    let imported = if rows.tickets.is_empty() || (query.atomic && !rows.errors.is_empty()) {
+        Vec::new()
+    } else {
+        store_for(&req).write().await.import(rows.tickets)?
+    };
+    let response_body = ImportTicketsResponse { imported, errors: rows.errors };
+
+    let mut response = Response::new(StatusCode::Ok);
+    response.set_body(Body::from_json(&response_body)?);
+    Ok(response)
+}
+
+#[utoipa::path(
+    get, path = "/tickets/export", params(ExportTicketsQuery),
+    responses(
+        (status = 200, content_type = "application/x-ndjson", body = String,
+            description = "All the tickets in id order, as JSON Lines or CSV"),
+        (status = 400, body = ErrorResponse),
+    ),
+)]
+pub async fn export_tickets<R: TicketRepository>(req: Request<TicketStore<R>>) -> tide::Result {
+    authorize(&req, Action::Read)?;
+    let query: ExportTicketsQuery = req.query()
+        .map_err(|e| MyError::BadRequest { field: "query", message: e.to_string() })?;
+
+    let (sender, receiver) = mpsc::channel(EXPORT_BATCH_SIZE);
+    tokio::spawn(forward_tickets(req.state().clone(), query.format, sender));
+
+    let mut response = Response::new(StatusCode::Ok);
+    response.insert_header("Content-Type", query.format.content_type());
+    response.set_body(Body::from_reader(BufReader::new(receiver.into_async_read()), None));
+    Ok(response)
+}
+
+/// Writes every ticket to `sender`, a batch at a time. Tickets changed during
+/// the export are written as they were when their batch was read.
+async fn forward_tickets<R: TicketRepository>(store: TicketStore<R>, format: BulkFormat, mut sender: mpsc::Sender<io::Result<String>>) {
+    if format == BulkFormat::Csv && sender.send(Ok(csv_record(CSV_COLUMNS))).await.is_err() {
+        return;
+    }
+    let mut after = None;
+    loop {
+        let batch = store.read().await.list(after, &TicketFilter::default(), EXPORT_BATCH_SIZE).await;
+        let Some(last) = batch.last() else {
+            return;
+        };
+        after = Some(last.id);
+        for ticket in batch {
+            if sender.send(Ok(format.encode(ticket))).await.is_err() {
+                // The client went away.
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,347 @@
+use std::time::Duration;
+use futures::io::Lines;
+use futures::{AsyncBufReadExt, StreamExt};
+use surf::http::Method;
+use surf::{Body, RequestBuilder, Response, StatusCode, Url};
+use thiserror::Error;
+use ticket_fields::{TicketAssignee, TicketComment};
+use crate::bulk::{BulkFormat, ExportTicketsQuery, ImportTicketsQuery, ImportTicketsResponse};
+use crate::data::{Comment, Ticket, TicketDraft, TicketFilter, TicketPatch};
+use crate::history::HistoryEntry;
+use crate::links::Relation;
+use crate::server::{
+    CreateCommentRequest, CreateTicketRequest, CreateTicketResponse, ErrorBody, ErrorResponse,
+    GetCommentResponse, GetTicketResponse, ListCommentsQuery, ListCommentsResponse, ListTicketsQuery,
+    ListLinksResponse, ListTicketsResponse, PatchTicketRequest, SearchTicketsQuery, SearchTicketsResponse,
+    TicketBlockersResponse, TicketEventData, TicketHistoryResponse, TicketLink,
+};
+use crate::store::{CommentId, TicketId};
+
+#[derive(Error, Debug)]
+pub enum ClientError {
+    #[error("Invalid request: {}", .0.message)]
+    InvalidRequest(ErrorBody),
+
+    #[error("Unauthorized: {}", .0.message)]
+    Unauthorized(ErrorBody),
+
+    #[error("Forbidden: {}", .0.message)]
+    Forbidden(ErrorBody),
+
+    #[error("Not found: {}", .0.message)]
+    NotFound(ErrorBody),
+
+    #[error("Precondition failed: {}", .0.message)]
+    PreconditionFailed(ErrorBody),
+
+    #[error("Conflict: {}", .0.message)]
+    Conflict(ErrorBody),
+
+    #[error("Rate limited: {}", .0.message)]
+    RateLimited(ErrorBody),
+
+    #[error("Server error ({status}): {message}")]
+    Server { status: StatusCode, message: String },
+
+    #[error("Connection failed: {0}")]
+    Connection(String),
+
+    /// The base URL of the client can't be joined with the path of a request.
+    #[error("Invalid URL: {0}")]
+    InvalidUrl(String),
+
+    #[error("Unexpected response: {0}")]
+    UnexpectedResponse(String),
+}
+
+impl ClientError {
+    /// The machine-readable code sent by the server, if any.
+    pub fn code(&self) -> Option<&str> {
+        match self {
+            ClientError::InvalidRequest(body)
+            | ClientError::Unauthorized(body)
+            | ClientError::Forbidden(body)
+            | ClientError::NotFound(body)
+            | ClientError::PreconditionFailed(body)
+            | ClientError::Conflict(body)
+            | ClientError::RateLimited(body) => Some(&body.code),
+            _ => None,
+        }
+    }
+}
+
+/// One page of `TicketClient::list_tickets`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TicketPage {
+    pub tickets: Vec<Ticket>,
+    pub next_after: Option<TicketId>,
+}
+
+/// One page of `TicketClient::list_comments`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CommentPage {
+    pub comments: Vec<Comment>,
+    pub next_after: Option<CommentId>,
+}
+
+/// The changes made to tickets, as streamed by `TicketClient::ticket_events`.
+pub struct TicketEvents {
+    lines: Lines<Response>,
+}
+
+impl TicketEvents {
+    /// The next event along with its id, or `None` once the server ended the stream.
+    pub async fn next(&mut self) -> Result<Option<(u64, TicketEventData)>, ClientError> {
+        let (mut id, mut data) = (None, None);
+        while let Some(line) = self.lines.next().await {
+            let line = line.map_err(|e| ClientError::Connection(e.to_string()))?;
+            if line.is_empty() {
+                if let (Some(id), Some(data)) = (id.take(), data.take()) {
+                    return Ok(Some((id, data)));
+                }
+            } else if let Some(value) = line.strip_prefix("id: ") {
+                id = value.parse().ok();
+            } else if let Some(value) = line.strip_prefix("data: ") {
+                let event = serde_json::from_str(value).map_err(|e| ClientError::UnexpectedResponse(e.to_string()))?;
+                data = Some(event);
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// An async client for the REST API exposed by `server::run_server`.
+///
+/// Requests that fail to reach the server are retried with exponential backoff,
+/// except ticket creation: a retried `POST` could create the same ticket twice.
+#[derive(Clone, Debug)]
+pub struct TicketClient {
+    base_url: Url,
+    http: surf::Client,
+    max_retries: u32,
+    backoff: Duration,
+    token: Option<String>,
+}
+
+impl TicketClient {
+    pub fn new(base_url: Url) -> Self {
+        TicketClient {
+            base_url,
+            http: surf::Client::new(),
+            max_retries: 3,
+            backoff: Duration::from_millis(100),
+            token: None,
+        }
+    }
+
+    /// How many times to retry a request after a connection error,
+    /// and how long to wait before the first retry. The delay doubles every time.
+    pub fn with_retries(mut self, max_retries: u32, backoff: Duration) -> Self {
+        self.max_retries = max_retries;
+        self.backoff = backoff;
+        self
+    }
+
+    /// Authenticates every request with `token`.
+    pub fn with_token(mut self, token: String) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    pub async fn create_ticket(&self, draft: TicketDraft) -> Result<TicketId, ClientError> {
+        let body = CreateTicketRequest::from(draft);
+        let mut response = self.send(Method::Post, "tickets", 0, |req| req.body_json(&body)).await?;
+        Ok(decode::<CreateTicketResponse>(&mut response).await?.ticket_id)
+    }
+
+    pub async fn get_ticket(&self, id: TicketId) -> Result<Ticket, ClientError> {
+        let path = format!("tickets/{}", id.0);
+        let mut response = self.send(Method::Get, &path, self.max_retries, Ok).await?;
+        Ok(decode::<GetTicketResponse>(&mut response).await?.0)
+    }
+
+    pub async fn list_tickets(&self, filter: &TicketFilter, after: Option<TicketId>, limit: Option<usize>) -> Result<TicketPage, ClientError> {
+        let query = ListTicketsQuery::new(filter, after, limit);
+        let mut response = self.send(Method::Get, "tickets", self.max_retries, |req| req.query(&query)).await?;
+        let page = decode::<ListTicketsResponse>(&mut response).await?;
+        Ok(TicketPage {
+            tickets: page.tickets.into_iter().map(|t| t.0).collect(),
+            next_after: page.next_after,
+        })
+    }
+
+    /// Links `ticket_id` to `other`: for instance, `Relation::BlockedBy` means `ticket_id` is blocked by `other`.
+    pub async fn link_tickets(&self, ticket_id: TicketId, relation: Relation, other: TicketId) -> Result<(), ClientError> {
+        let path = format!("tickets/{}/links", ticket_id.0);
+        let body = TicketLink { relation, ticket_id: other };
+        self.send(Method::Post, &path, self.max_retries, |req| req.body_json(&body)).await?;
+        Ok(())
+    }
+
+    /// Succeeds even if the tickets were not linked.
+    pub async fn unlink_tickets(&self, ticket_id: TicketId, relation: Relation, other: TicketId) -> Result<(), ClientError> {
+        let path = format!("tickets/{}/links", ticket_id.0);
+        let query = TicketLink { relation, ticket_id: other };
+        self.send(Method::Delete, &path, self.max_retries, |req| req.query(&query)).await?;
+        Ok(())
+    }
+
+    /// How `ticket_id` relates to each ticket it is linked to.
+    pub async fn ticket_links(&self, ticket_id: TicketId) -> Result<Vec<TicketLink>, ClientError> {
+        let path = format!("tickets/{}/links", ticket_id.0);
+        let mut response = self.send(Method::Get, &path, self.max_retries, Ok).await?;
+        Ok(decode::<ListLinksResponse>(&mut response).await?.links)
+    }
+
+    /// The tickets blocking `ticket_id`, directly or not, closest first.
+    pub async fn ticket_blockers(&self, ticket_id: TicketId) -> Result<Vec<TicketId>, ClientError> {
+        let path = format!("tickets/{}/blockers", ticket_id.0);
+        let mut response = self.send(Method::Get, &path, self.max_retries, Ok).await?;
+        Ok(decode::<TicketBlockersResponse>(&mut response).await?.blockers)
+    }
+
+    /// Posts a comment as `author`, which defaults to the authenticated user.
+    pub async fn add_comment(&self, ticket_id: TicketId, author: Option<TicketAssignee>, body: TicketComment) -> Result<Comment, ClientError> {
+        let path = format!("tickets/{}/comments", ticket_id.0);
+        let body = CreateCommentRequest { author: author.map(|author| author.0), body: body.0 };
+        let mut response = self.send(Method::Post, &path, 0, |req| req.body_json(&body)).await?;
+        Ok(decode::<GetCommentResponse>(&mut response).await?.0)
+    }
+
+    pub async fn list_comments(&self, ticket_id: TicketId, after: Option<CommentId>, limit: Option<usize>) -> Result<CommentPage, ClientError> {
+        let path = format!("tickets/{}/comments", ticket_id.0);
+        let query = ListCommentsQuery { after: after.map(|id| id.0), limit };
+        let mut response = self.send(Method::Get, &path, self.max_retries, |req| req.query(&query)).await?;
+        let page = decode::<ListCommentsResponse>(&mut response).await?;
+        Ok(CommentPage {
+            comments: page.comments.into_iter().map(|c| c.0).collect(),
+            next_after: page.next_after,
+        })
+    }
+
+    /// Every change applied to a ticket, oldest first. Deleted tickets keep their history.
+    pub async fn ticket_history(&self, id: TicketId) -> Result<Vec<HistoryEntry>, ClientError> {
+        let path = format!("tickets/{}/history", id.0);
+        let mut response = self.send(Method::Get, &path, self.max_retries, Ok).await?;
+        Ok(decode::<TicketHistoryResponse>(&mut response).await?.entries)
+    }
+
+    /// Imports the tickets in `body`, which is streamed to the server as it is read.
+    /// Rows that fail validation are reported rather than returned as an error.
+    pub async fn import_tickets(&self, format: BulkFormat, body: Body, atomic: bool) -> Result<ImportTicketsResponse, ClientError> {
+        let query = ImportTicketsQuery { format, atomic };
+        let mut body = Some(body);
+        // The body can only be sent once, so there are no retries.
+        let mut response = self.send(Method::Post, "tickets/import", 0, |req| {
+            let req = req.query(&query)?.content_type(format.content_type());
+            Ok(match body.take() {
+                Some(body) => req.body(body),
+                None => req,
+            })
+        }).await?;
+        decode(&mut response).await
+    }
+
+    /// Every ticket in id order, as a body to be read while the server writes it.
+    pub async fn export_tickets(&self, format: BulkFormat) -> Result<Body, ClientError> {
+        let query = ExportTicketsQuery { format };
+        let mut response = self.send(Method::Get, "tickets/export", self.max_retries, |req| req.query(&query)).await?;
+        Ok(response.take_body())
+    }
+
+    /// Returns the tickets matching `query`, best matches first, with their score.
+    pub async fn search_tickets(&self, query: &str, limit: Option<usize>) -> Result<Vec<(Ticket, f64)>, ClientError> {
+        let query = SearchTicketsQuery { q: query.to_string(), limit };
+        let mut response = self.send(Method::Get, "tickets/search", self.max_retries, |req| req.query(&query)).await?;
+        let result = decode::<SearchTicketsResponse>(&mut response).await?;
+        Ok(result.hits.into_iter().map(|hit| (hit.ticket.0, hit.score)).collect())
+    }
+
+    /// Applies `patch`, only if the ticket is still at `expected_version` when one is given.
+    pub async fn patch_ticket(&self, id: TicketId, patch: TicketPatch, expected_version: Option<u64>) -> Result<Ticket, ClientError> {
+        let path = format!("tickets/{}", id.0);
+        let body = PatchTicketRequest::from(patch);
+        let mut response = self.send(Method::Patch, &path, self.max_retries, |req| {
+            if_match(req, expected_version).body_json(&body)
+        }).await?;
+        Ok(decode::<GetTicketResponse>(&mut response).await?.0)
+    }
+
+    /// Streams the changes made to tickets from now on, or since the event `last_seen`.
+    pub async fn ticket_events(&self, last_seen: Option<u64>) -> Result<TicketEvents, ClientError> {
+        let response = self.send(Method::Get, "tickets/events", self.max_retries, |req| Ok(match last_seen {
+            Some(id) => req.header("Last-Event-ID", id.to_string()),
+            None => req,
+        })).await?;
+        Ok(TicketEvents { lines: response.lines() })
+    }
+
+    pub async fn delete_ticket(&self, id: TicketId, expected_version: Option<u64>) -> Result<(), ClientError> {
+        let path = format!("tickets/{}", id.0);
+        self.send(Method::Delete, &path, self.max_retries, |req| Ok(if_match(req, expected_version))).await?;
+        Ok(())
+    }
+
+    /// Sends a request built by `build`, retrying up to `retries` times if the server
+    /// cannot be reached, and turns error statuses into a `ClientError`.
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        retries: u32,
+        mut build: impl FnMut(RequestBuilder) -> surf::Result<RequestBuilder>,
+    ) -> Result<Response, ClientError> {
+        let url = self.base_url.join(path).map_err(|e| ClientError::InvalidUrl(e.to_string()))?;
+        let mut delay = self.backoff;
+        let mut attempt = 0;
+        let mut response = loop {
+            let mut request = RequestBuilder::new(method, url.clone());
+            if let Some(token) = &self.token {
+                request = request.header("Authorization", format!("Bearer {}", token));
+            }
+            let request = build(request).map_err(|e| ClientError::UnexpectedResponse(e.to_string()))?;
+            match self.http.send(request).await {
+                Ok(response) => break response,
+                Err(e) if attempt >= retries => return Err(ClientError::Connection(e.to_string())),
+                Err(_) => {
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                    attempt += 1;
+                }
+            }
+        };
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        let body = match response.body_json::<ErrorResponse>().await {
+            Ok(response) => response.error,
+            Err(e) => {
+                let message = format!("{} with no error body: {}", status, e);
+                return Err(ClientError::Server { status, message });
+            }
+        };
+        Err(match status {
+            StatusCode::BadRequest => ClientError::InvalidRequest(body),
+            StatusCode::Unauthorized => ClientError::Unauthorized(body),
+            StatusCode::Forbidden => ClientError::Forbidden(body),
+            StatusCode::NotFound => ClientError::NotFound(body),
+            StatusCode::PreconditionFailed => ClientError::PreconditionFailed(body),
+            StatusCode::Conflict => ClientError::Conflict(body),
+            StatusCode::TooManyRequests => ClientError::RateLimited(body),
+            _ => ClientError::Server { status, message: body.message },
+        })
+    }
+}
+
+fn if_match(request: RequestBuilder, expected_version: Option<u64>) -> RequestBuilder {
+    match expected_version {
+        Some(version) => request.header("If-Match", format!("\"{}\"", version)),
+        None => request,
+    }
+}
+
+async fn decode<T: serde::de::DeserializeOwned>(response: &mut Response) -> Result<T, ClientError> {
+    response.body_json().await.map_err(|e| ClientError::UnexpectedResponse(e.to_string()))
+}
@@ -0,0 +1,261 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use clap::{Args, Parser};
+use serde::Deserialize;
+use thiserror::Error;
+use tide::log::LevelFilter;
+use crate::auth::Tokens;
+use crate::middleware::RateLimit;
+use crate::policy::Policy;
+use crate::server::ServerSettings;
+use crate::workflow::Workflow;
+
+/// Everything the server binary can be configured with.
+///
+/// Each setting is taken from, in order of precedence: command-line flags,
+/// `TICKETS_*` environment variables, the TOML configuration file, defaults.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    pub bind_address: IpAddr,
+    pub port: u16,
+    pub storage_path: PathBuf,
+    pub max_body_size: usize,
+    pub max_import_size: usize,
+    /// Requests per second allowed to each client, or 0 for no limit.
+    pub rate_limit: u32,
+    pub rate_limit_burst: u32,
+    pub shutdown_timeout: Duration,
+    pub log_level: LevelFilter,
+    pub workflow: Workflow,
+    /// Whether requests must carry a token, as issued by the `tokens` binary.
+    pub require_auth: bool,
+    pub policy: Policy,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 8080,
+            storage_path: PathBuf::from("tickets-data"),
+            max_body_size: 64 * 1024,
+            max_import_size: 64 * 1024 * 1024,
+            rate_limit: RateLimit::default().per_second,
+            rate_limit_burst: RateLimit::default().burst,
+            shutdown_timeout: Duration::from_secs(30),
+            log_level: LevelFilter::Info,
+            workflow: Workflow::default(),
+            require_auth: false,
+            policy: Policy::default(),
+        }
+    }
+}
+
+/// One source of settings: every field is optional,
+/// missing ones are taken from the layers below.
+#[derive(Args, Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigLayer {
+    /// IP address to listen on, e.g. 0.0.0.0 to accept connections from anywhere.
+    #[arg(long, env = "TICKETS_BIND_ADDRESS")]
+    pub bind_address: Option<IpAddr>,
+
+    #[arg(long, env = "TICKETS_PORT")]
+    pub port: Option<u16>,
+
+    /// Directory where tickets are persisted.
+    #[arg(long, env = "TICKETS_STORAGE_PATH")]
+    pub storage_path: Option<PathBuf>,
+
+    /// Maximum size of a request body, in bytes.
+    #[arg(long, env = "TICKETS_MAX_BODY_SIZE")]
+    pub max_body_size: Option<usize>,
+
+    /// Maximum size of a bulk import, in bytes.
+    #[arg(long, env = "TICKETS_MAX_IMPORT_SIZE")]
+    pub max_import_size: Option<usize>,
+
+    /// Requests per second allowed to each API token, and failed authentications to each IP address.
+    /// Without authentication, requests to each IP address.
+    /// 0 disables rate limiting.
+    #[arg(long, env = "TICKETS_RATE_LIMIT")]
+    pub rate_limit: Option<u32>,
+
+    /// Requests a client can make at once, before being limited to `rate_limit` per second.
+    #[arg(long, env = "TICKETS_RATE_LIMIT_BURST")]
+    pub rate_limit_burst: Option<u32>,
+
+    /// Seconds in-flight requests are given to complete when shutting down.
+    #[arg(long, env = "TICKETS_SHUTDOWN_TIMEOUT")]
+    pub shutdown_timeout: Option<u64>,
+
+    /// One of off, error, warn, info, debug, trace.
+    #[arg(long, env = "TICKETS_LOG_LEVEL")]
+    pub log_level: Option<String>,
+
+    /// Reject requests without a valid `Authorization: Bearer` token.
+    #[arg(long, env = "TICKETS_REQUIRE_AUTH")]
+    pub require_auth: Option<bool>,
+
+    /// Allowed status changes, e.g. `ToDo = ["InProgress"]`. Only set from the file.
+    #[arg(skip)]
+    pub workflow: Option<Workflow>,
+
+    /// Roles of users, and the role each action requires. Only set from the file.
+    #[arg(skip)]
+    pub policy: Option<Policy>,
+}
+
+#[derive(Parser, Debug)]
+#[command(about = "Serve the ticket management REST API")]
+struct Cli {
+    /// Path of a TOML configuration file.
+    #[arg(long, env = "TICKETS_CONFIG")]
+    config: Option<PathBuf>,
+
+    #[command(flatten)]
+    overrides: ConfigLayer,
+}
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Cannot read configuration file {path}: {source}")]
+    Read { path: PathBuf, source: std::io::Error },
+
+    #[error("Invalid configuration file {path}: {source}")]
+    Parse { path: PathBuf, source: toml::de::Error },
+
+    #[error("Invalid {setting}: {message}")]
+    Invalid { setting: &'static str, message: String },
+}
+
+impl ConfigLayer {
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|source| ConfigError::Read { path: path.to_path_buf(), source })?;
+        toml::from_str(&content)
+            .map_err(|source| ConfigError::Parse { path: path.to_path_buf(), source })
+    }
+
+    /// Settings of `self` win over the ones of `lower`.
+    pub fn or(self, lower: ConfigLayer) -> ConfigLayer {
+        ConfigLayer {
+            bind_address: self.bind_address.or(lower.bind_address),
+            port: self.port.or(lower.port),
+            storage_path: self.storage_path.or(lower.storage_path),
+            max_body_size: self.max_body_size.or(lower.max_body_size),
+            max_import_size: self.max_import_size.or(lower.max_import_size),
+            rate_limit: self.rate_limit.or(lower.rate_limit),
+            rate_limit_burst: self.rate_limit_burst.or(lower.rate_limit_burst),
+            shutdown_timeout: self.shutdown_timeout.or(lower.shutdown_timeout),
+            log_level: self.log_level.or(lower.log_level),
+            workflow: self.workflow.or(lower.workflow),
+            require_auth: self.require_auth.or(lower.require_auth),
+            policy: self.policy.or(lower.policy),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the configuration from the command line, the environment
+    /// and the configuration file they point to.
+    pub fn load() -> Result<Self, ConfigError> {
+        let cli = Cli::parse();
+        let file = match cli.config {
+            Some(path) => ConfigLayer::from_file(&path)?,
+            None => ConfigLayer::default(),
+        };
+        Config::from_layer(cli.overrides.or(file))
+    }
+
+    /// Fills the gaps of `layer` with defaults, and validates the result.
+    pub fn from_layer(layer: ConfigLayer) -> Result<Self, ConfigError> {
+        let defaults = Config::default();
+        let log_level = match layer.log_level {
+            Some(level) => level.parse().map_err(|_| ConfigError::Invalid {
+                setting: "log_level",
+                message: format!("{:?} is not one of off, error, warn, info, debug, trace", level),
+            })?,
+            None => defaults.log_level,
+        };
+        let config = Config {
+            bind_address: layer.bind_address.unwrap_or(defaults.bind_address),
+            port: layer.port.unwrap_or(defaults.port),
+            storage_path: layer.storage_path.unwrap_or(defaults.storage_path),
+            max_body_size: layer.max_body_size.unwrap_or(defaults.max_body_size),
+            max_import_size: layer.max_import_size.unwrap_or(defaults.max_import_size),
+            rate_limit: layer.rate_limit.unwrap_or(defaults.rate_limit),
+            rate_limit_burst: layer.rate_limit_burst.unwrap_or(defaults.rate_limit_burst),
+            shutdown_timeout: layer.shutdown_timeout.map(Duration::from_secs).unwrap_or(defaults.shutdown_timeout),
+            log_level,
+            workflow: layer.workflow.unwrap_or(defaults.workflow),
+            require_auth: layer.require_auth.unwrap_or(defaults.require_auth),
+            policy: layer.policy.unwrap_or(defaults.policy),
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.max_body_size == 0 {
+            return Err(ConfigError::Invalid {
+                setting: "max_body_size",
+                message: "must be greater than 0".to_string(),
+            });
+        }
+        if self.max_import_size == 0 {
+            return Err(ConfigError::Invalid {
+                setting: "max_import_size",
+                message: "must be greater than 0".to_string(),
+            });
+        }
+        if self.rate_limit > 0 && self.rate_limit_burst == 0 {
+            return Err(ConfigError::Invalid {
+                setting: "rate_limit_burst",
+                message: "must be greater than 0 when rate_limit is set".to_string(),
+            });
+        }
+        if self.policy != Policy::default() && !self.require_auth {
+            return Err(ConfigError::Invalid {
+                setting: "policy",
+                message: "is only enforced when require_auth is set".to_string(),
+            });
+        }
+        if self.storage_path.as_os_str().is_empty() {
+            return Err(ConfigError::Invalid {
+                setting: "storage_path",
+                message: "cannot be empty".to_string(),
+            });
+        }
+        if self.storage_path.exists() && !self.storage_path.is_dir() {
+            return Err(ConfigError::Invalid {
+                setting: "storage_path",
+                message: format!("{} is not a directory", self.storage_path.display()),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn socket_address(&self) -> SocketAddr {
+        SocketAddr::new(self.bind_address, self.port)
+    }
+
+    /// Tokens are read from the storage directory if authentication is required.
+    pub fn server_settings(&self) -> io::Result<ServerSettings> {
+        let tokens = match self.require_auth {
+            true => Some(Arc::new(Tokens::open(&self.storage_path)?)),
+            false => None,
+        };
+        Ok(ServerSettings {
+            max_body_size: self.max_body_size,
+            max_import_size: self.max_import_size,
+            shutdown_timeout: self.shutdown_timeout,
+            tokens,
+            policy: self.policy.clone(),
+            rate_limit: (self.rate_limit > 0).then_some(RateLimit { per_second: self.rate_limit, burst: self.rate_limit_burst }),
+        })
+    }
+}
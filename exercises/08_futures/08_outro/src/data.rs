@@ -1,7 +1,14 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use serde::Serialize;
+use thiserror::Error;
 use tide::convert::Deserialize;
-use crate::store::TicketId;
-use ticket_fields::{TicketDescription, TicketTitle};
+use utoipa::ToSchema;
+use crate::store::{CommentId, TicketId};
+use crate::workflow::{IllegalTransition, Workflow};
+use ticket_fields::{TicketAssignee, TicketComment, TicketDescription, TicketLabel, TicketTitle};
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Ticket {
@@ -9,17 +16,189 @@ pub struct Ticket {
     pub title: TicketTitle,
     pub description: TicketDescription,
     pub status: Status,
+    pub priority: Priority,
+    pub assignee: Option<TicketAssignee>,
+    pub labels: BTreeSet<TicketLabel>,
+    /// Bumped on every change, starting from 1 when the ticket is created.
+    pub version: u64,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct TicketDraft {
     pub title: TicketTitle,
     pub description: TicketDescription,
+    pub priority: Priority,
+    pub assignee: Option<TicketAssignee>,
+    pub labels: BTreeSet<TicketLabel>,
 }
 
-#[derive(Clone, Debug, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct TicketPatch {
+    pub title: Option<TicketTitle>,
+    pub description: Option<TicketDescription>,
+    pub status: Option<Status>,
+    pub priority: Option<Priority>,
+    /// `Some(None)` unassigns the ticket.
+    pub assignee: Option<Option<TicketAssignee>>,
+    /// Replaces all the labels.
+    pub labels: Option<BTreeSet<TicketLabel>>,
+}
+
+/// A comment left on a ticket. Comments can't be edited, and are deleted with their ticket.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Comment {
+    /// Comments of a ticket are numbered from 0, in the order they were added.
+    pub id: CommentId,
+    pub ticket_id: TicketId,
+    /// Follows the same rules as assignees.
+    pub author: TicketAssignee,
+    pub body: TicketComment,
+    pub created_at: SystemTime,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommentDraft {
+    pub author: TicketAssignee,
+    pub body: TicketComment,
+}
+
+/// Which tickets to list: all the given criteria must match.
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct TicketFilter {
+    pub status: Option<Status>,
+    pub priority: Option<Priority>,
+    pub assignee: Option<TicketAssignee>,
+    /// Tickets must have this label, among others.
+    pub label: Option<TicketLabel>,
+}
+
+impl TicketDraft {
+    /// A draft with the default priority, no assignee and no labels.
+    pub fn new(title: TicketTitle, description: TicketDescription) -> Self {
+        TicketDraft { title, description, priority: Priority::default(), assignee: None, labels: BTreeSet::new() }
+    }
+}
+
+impl TicketPatch {
+    /// Whether it changes anything but the status.
+    pub fn edits_fields(&self) -> bool {
+        *self != TicketPatch { status: self.status, ..Default::default() }
+    }
+}
+
+impl TicketFilter {
+    pub fn matches(&self, ticket: &Ticket) -> bool {
+        self.status.is_none_or(|status| ticket.status == status)
+            && self.priority.is_none_or(|priority| ticket.priority == priority)
+            && self.assignee.as_ref().is_none_or(|assignee| ticket.assignee.as_ref() == Some(assignee))
+            && self.label.as_ref().is_none_or(|label| ticket.labels.contains(label))
+    }
+}
+
+/// The current time, truncated to the milliseconds kept by timestamps once serialized.
+pub fn now() -> SystemTime {
+    let since_epoch = UNIX_EPOCH.elapsed().unwrap_or_default();
+    UNIX_EPOCH + Duration::from_millis(since_epoch.as_millis() as u64)
+}
+
+impl Ticket {
+    /// Applies `patch`, unless it changes the status in a way `workflow` doesn't allow.
+    /// The version is only bumped if a field actually changes.
+    pub fn apply(&mut self, patch: TicketPatch, workflow: &Workflow) -> Result<(), IllegalTransition> {
+        if let Some(status) = patch.status {
+            workflow.check(self.status, status)?;
+        }
+        let before = self.clone();
+        if let Some(title) = patch.title {
+            self.title = title;
+        }
+        if let Some(description) = patch.description {
+            self.description = description;
+        }
+        if let Some(status) = patch.status {
+            self.status = status;
+        }
+        if let Some(priority) = patch.priority {
+            self.priority = priority;
+        }
+        if let Some(assignee) = patch.assignee {
+            self.assignee = assignee;
+        }
+        if let Some(labels) = patch.labels {
+            self.labels = labels;
+        }
+        if *self != before {
+            self.version += 1;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
 pub enum Status {
     ToDo,
     InProgress,
+    Blocked,
+    InReview,
     Done,
 }
+
+impl Status {
+    pub const ALL: [Status; 5] = [Status::ToDo, Status::InProgress, Status::Blocked, Status::InReview, Status::Done];
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("Unknown status {0}, expected one of ToDo, InProgress, Blocked, InReview, Done")]
+pub struct UnknownStatus(pub String);
+
+impl FromStr for Status {
+    type Err = UnknownStatus;
+
+    /// Parses the same names used in JSON, ignoring case, dashes and underscores.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.replace(['-', '_'], "");
+        Status::ALL.into_iter()
+            .find(|status| status.to_string().eq_ignore_ascii_case(&name))
+            .ok_or_else(|| UnknownStatus(s.to_string()))
+    }
+}
+
+#[derive(Clone, Debug, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+pub enum Priority {
+    Low,
+    #[default]
+    Medium,
+    High,
+    Critical,
+}
+
+impl Priority {
+    pub const ALL: [Priority; 4] = [Priority::Low, Priority::Medium, Priority::High, Priority::Critical];
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("Unknown priority {0}, expected one of Low, Medium, High, Critical")]
+pub struct UnknownPriority(pub String);
+
+impl FromStr for Priority {
+    type Err = UnknownPriority;
+
+    /// Parses the same names used in JSON, ignoring case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Priority::ALL.into_iter()
+            .find(|priority| priority.to_string().eq_ignore_ascii_case(s))
+            .ok_or_else(|| UnknownPriority(s.to_string()))
+    }
+}
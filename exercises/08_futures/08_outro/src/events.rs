@@ -0,0 +1,109 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+use crate::data::Ticket;
+use crate::store::TicketId;
+
+/// How many past events are kept around for subscribers that resume a stream.
+pub const DEFAULT_BACKLOG: usize = 1024;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TicketChange {
+    Created(Ticket),
+    Updated(Ticket),
+    Deleted(TicketId),
+}
+
+impl TicketChange {
+    pub fn ticket_id(&self) -> TicketId {
+        match self {
+            TicketChange::Created(ticket) | TicketChange::Updated(ticket) => ticket.id,
+            TicketChange::Deleted(id) => *id,
+        }
+    }
+}
+
+/// A change, numbered in the order it was applied to the store.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TicketEvent {
+    pub sequence: u64,
+    pub change: TicketChange,
+}
+
+/// Fans out the changes of a store to any number of subscribers.
+pub struct EventBus {
+    inner: Mutex<EventBusInner>,
+}
+
+struct EventBusInner {
+    /// `None` once closed.
+    sender: Option<broadcast::Sender<TicketEvent>>,
+    backlog: VecDeque<TicketEvent>,
+    capacity: usize,
+    next_sequence: u64,
+}
+
+/// What a new subscriber gets: the events it missed, then the live ones.
+pub struct Subscription {
+    pub missed: Vec<TicketEvent>,
+    pub receiver: broadcast::Receiver<TicketEvent>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new(DEFAULT_BACKLOG)
+    }
+}
+
+impl EventBus {
+    /// Keeps the last `capacity` events, which is also how far behind a
+    /// subscriber can fall before it gets disconnected.
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        let (sender, _) = broadcast::channel(capacity);
+        EventBus {
+            inner: Mutex::new(EventBusInner {
+                sender: Some(sender),
+                backlog: VecDeque::with_capacity(capacity),
+                capacity,
+                next_sequence: 0,
+            }),
+        }
+    }
+
+    pub fn publish(&self, change: TicketChange) {
+        let mut inner = self.inner.lock().unwrap();
+        let event = TicketEvent { sequence: inner.next_sequence, change };
+        inner.next_sequence += 1;
+        if inner.backlog.len() == inner.capacity {
+            inner.backlog.pop_front();
+        }
+        inner.backlog.push_back(event.clone());
+        if let Some(sender) = &inner.sender {
+            // No receivers is not an error: nobody is listening right now.
+            let _ = sender.send(event);
+        }
+    }
+
+    /// Subscribes to the events published from now on and, if `last_seen` is given,
+    /// to the ones after it that are still in the backlog.
+    ///
+    /// Sequence numbers start over when the server restarts: a `last_seen` from the
+    /// future is taken as coming from a previous run, and the whole backlog is replayed.
+    /// Returns `None` if the bus has been closed.
+    pub fn subscribe(&self, last_seen: Option<u64>) -> Option<Subscription> {
+        let inner = self.inner.lock().unwrap();
+        let receiver = inner.sender.as_ref()?.subscribe();
+        let missed = match last_seen {
+            None => Vec::new(),
+            Some(last) if last >= inner.next_sequence => inner.backlog.iter().cloned().collect(),
+            Some(last) => inner.backlog.iter().filter(|e| e.sequence > last).cloned().collect(),
+        };
+        Some(Subscription { missed, receiver })
+    }
+
+    /// Ends all subscriptions, and refuses new ones.
+    pub fn close(&self) {
+        self.inner.lock().unwrap().sender = None;
+    }
+}
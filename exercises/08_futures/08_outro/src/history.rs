@@ -0,0 +1,93 @@
+use std::time::SystemTime;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use ticket_fields::TicketAssignee;
+use utoipa::ToSchema;
+use crate::data::{self, Ticket};
+use crate::server::{rfc3339, TicketSerializer};
+use crate::store::TicketId;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryAction {
+    Created,
+    Updated,
+    Deleted,
+}
+
+/// A field of a ticket, as it was before and after a change.
+/// Fields that did not exist are `null`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct FieldChange {
+    pub field: String,
+    pub old: Value,
+    pub new: Value,
+}
+
+/// A mutation applied to a ticket. Entries are never modified once recorded.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct HistoryEntry {
+    pub ticket_id: TicketId,
+    /// The version of the ticket after the change, or before it for deletions.
+    pub version: u64,
+    /// When the change was applied, as an RFC 3339 timestamp.
+    #[serde(with = "rfc3339")]
+    #[schema(value_type = String, format = DateTime)]
+    pub timestamp: SystemTime,
+    pub action: HistoryAction,
+    /// The authenticated user who made the change, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actor: Option<String>,
+    /// Creations and deletions are compared against a ticket whose fields are all `null`.
+    pub changes: Vec<FieldChange>,
+}
+
+impl HistoryEntry {
+    pub fn created(ticket: &Ticket) -> Self {
+        Self::new(ticket.id, ticket.version, HistoryAction::Created, changes(None, Some(ticket)))
+    }
+
+    pub fn updated(old: &Ticket, new: &Ticket) -> Self {
+        Self::new(new.id, new.version, HistoryAction::Updated, changes(Some(old), Some(new)))
+    }
+
+    pub fn deleted(ticket: &Ticket) -> Self {
+        Self::new(ticket.id, ticket.version, HistoryAction::Deleted, changes(Some(ticket), None))
+    }
+
+    fn new(ticket_id: TicketId, version: u64, action: HistoryAction, changes: Vec<FieldChange>) -> Self {
+        HistoryEntry { ticket_id, version, timestamp: data::now(), action, actor: None, changes }
+    }
+
+    pub fn by(mut self, actor: Option<&TicketAssignee>) -> Self {
+        self.actor = actor.map(|actor| actor.0.clone());
+        self
+    }
+}
+
+/// The fields of a ticket as they appear in the API, minus the bookkeeping ones.
+fn fields(ticket: Option<&Ticket>) -> Map<String, Value> {
+    let Some(ticket) = ticket else {
+        return Map::new();
+    };
+    let Ok(Value::Object(mut fields)) = TicketSerializer::serialize(ticket, serde_json::value::Serializer) else {
+        unreachable!("tickets are serialized as JSON objects");
+    };
+    fields.remove("id");
+    fields.remove("version");
+    fields
+}
+
+fn changes(old: Option<&Ticket>, new: Option<&Ticket>) -> Vec<FieldChange> {
+    let mut old = fields(old);
+    let new = fields(new);
+    let mut changes = Vec::new();
+    for (field, new) in new {
+        let old = old.remove(&field).unwrap_or(Value::Null);
+        if old != new {
+            changes.push(FieldChange { field, old, new });
+        }
+    }
+    changes.extend(old.into_iter().map(|(field, old)| FieldChange { field, old, new: Value::Null }));
+    changes
+}
@@ -8,6 +8,23 @@
 //
 // Use Rust's package registry, crates.io, to find the dependencies you need
 // (if any) to build this system.
+pub mod auth;
+pub mod bulk;
+pub mod client;
+pub mod config;
 pub mod data;
+pub mod events;
+pub mod history;
+pub mod links;
+pub mod metrics;
+pub mod middleware;
+pub mod openapi;
+pub mod persistence;
+pub mod policy;
+pub mod repository;
+pub mod search;
 pub mod store;
-pub mod server;
\ No newline at end of file
+pub mod server;
+pub mod shutdown;
+pub mod websocket;
+pub mod workflow;
\ No newline at end of file
@@ -0,0 +1,340 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use utoipa::ToSchema;
+use crate::store::TicketId;
+
+/// How the source of a link relates to its target.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkKind {
+    /// The target can't be completed before the source.
+    Blocks,
+    Duplicates,
+    /// The target is the parent of the source.
+    SubtaskOf,
+}
+
+/// A directed link between two tickets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+pub struct Link {
+    pub from: TicketId,
+    pub kind: LinkKind,
+    pub to: TicketId,
+}
+
+impl Link {
+    pub fn involves(&self, id: TicketId) -> bool {
+        self.from == id || self.to == id
+    }
+}
+
+/// How a ticket relates to another one, seen from either end of a link.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Relation {
+    Blocks,
+    BlockedBy,
+    Duplicates,
+    DuplicatedBy,
+    SubtaskOf,
+    ParentOf,
+}
+
+impl Relation {
+    pub const ALL: [Relation; 6] = [
+        Relation::Blocks,
+        Relation::BlockedBy,
+        Relation::Duplicates,
+        Relation::DuplicatedBy,
+        Relation::SubtaskOf,
+        Relation::ParentOf,
+    ];
+
+    /// The link stating that `ticket` has this relation to `other`.
+    pub fn link(self, ticket: TicketId, other: TicketId) -> Link {
+        let (from, kind, to) = match self {
+            Relation::Blocks => (ticket, LinkKind::Blocks, other),
+            Relation::BlockedBy => (other, LinkKind::Blocks, ticket),
+            Relation::Duplicates => (ticket, LinkKind::Duplicates, other),
+            Relation::DuplicatedBy => (other, LinkKind::Duplicates, ticket),
+            Relation::SubtaskOf => (ticket, LinkKind::SubtaskOf, other),
+            Relation::ParentOf => (other, LinkKind::SubtaskOf, ticket),
+        };
+        Link { from, kind, to }
+    }
+
+    /// How `ticket` relates to the other end of `link`, which must involve it.
+    pub fn of(ticket: TicketId, link: &Link) -> (Relation, TicketId) {
+        match (link.kind, link.from == ticket) {
+            (LinkKind::Blocks, true) => (Relation::Blocks, link.to),
+            (LinkKind::Blocks, false) => (Relation::BlockedBy, link.from),
+            (LinkKind::Duplicates, true) => (Relation::Duplicates, link.to),
+            (LinkKind::Duplicates, false) => (Relation::DuplicatedBy, link.from),
+            (LinkKind::SubtaskOf, true) => (Relation::SubtaskOf, link.to),
+            (LinkKind::SubtaskOf, false) => (Relation::ParentOf, link.from),
+        }
+    }
+}
+
+/// Same names as in JSON.
+impl fmt::Display for Relation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Relation::Blocks => "blocks",
+            Relation::BlockedBy => "blocked_by",
+            Relation::Duplicates => "duplicates",
+            Relation::DuplicatedBy => "duplicated_by",
+            Relation::SubtaskOf => "subtask_of",
+            Relation::ParentOf => "parent_of",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("Unknown relation {0}, expected one of blocks, blocked_by, duplicates, duplicated_by, subtask_of, parent_of")]
+pub struct UnknownRelation(pub String);
+
+impl FromStr for Relation {
+    type Err = UnknownRelation;
+
+    /// Parses the same names used in JSON, ignoring case, dashes and underscores.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.replace(['-', '_'], "");
+        Relation::ALL.into_iter()
+            .find(|relation| relation.to_string().replace('_', "").eq_ignore_ascii_case(&name))
+            .ok_or_else(|| UnknownRelation(s.to_string()))
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum LinkError {
+    #[error("A ticket cannot be linked to itself")]
+    SelfLink,
+
+    #[error("Linking ticket {} to ticket {} would create a cycle", .0.from.0, .0.to.0)]
+    Cycle(Link),
+
+    #[error("Ticket {} is already a subtask of ticket {}", .ticket.0, .parent.0)]
+    ParentAlreadySet { ticket: TicketId, parent: TicketId },
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// The links between tickets, indexed by both of their ends.
+///
+/// Blocking links and parent chains can't form cycles, and a ticket
+/// can only be the subtask of a single parent.
+#[derive(Debug, Default)]
+pub struct LinkGraph {
+    /// For each ticket, the links it is the source of.
+    outgoing: BTreeMap<TicketId, BTreeSet<(LinkKind, TicketId)>>,
+    /// For each ticket, the links it is the target of.
+    incoming: BTreeMap<TicketId, BTreeSet<(LinkKind, TicketId)>>,
+}
+
+impl LinkGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether `link` can be added to the graph.
+    pub fn check(&self, link: &Link) -> Result<(), LinkError> {
+        if link.from == link.to {
+            return Err(LinkError::SelfLink);
+        }
+        if link.kind == LinkKind::SubtaskOf {
+            if let Some(parent) = self.targets(link.from, LinkKind::SubtaskOf).next() {
+                if parent != link.to {
+                    return Err(LinkError::ParentAlreadySet { ticket: link.from, parent });
+                }
+            }
+        }
+        let acyclic = matches!(link.kind, LinkKind::Blocks | LinkKind::SubtaskOf);
+        if acyclic && self.reaches(link.to, link.kind, link.from) {
+            return Err(LinkError::Cycle(*link));
+        }
+        Ok(())
+    }
+
+    /// Adds `link` without checking it. Returns whether it is new.
+    pub fn insert(&mut self, link: Link) -> bool {
+        self.incoming.entry(link.to).or_default().insert((link.kind, link.from));
+        self.outgoing.entry(link.from).or_default().insert((link.kind, link.to))
+    }
+
+    /// Returns whether `link` was there.
+    pub fn remove(&mut self, link: &Link) -> bool {
+        remove_edge(&mut self.incoming, link.to, (link.kind, link.from));
+        remove_edge(&mut self.outgoing, link.from, (link.kind, link.to))
+    }
+
+    /// Removes every link involving `id`.
+    pub fn remove_ticket(&mut self, id: TicketId) {
+        for link in self.links_of(id) {
+            self.remove(&link);
+        }
+    }
+
+    pub fn contains(&self, link: &Link) -> bool {
+        self.outgoing.get(&link.from).is_some_and(|targets| targets.contains(&(link.kind, link.to)))
+    }
+
+    /// The links `id` is the source or the target of.
+    pub fn links_of(&self, id: TicketId) -> Vec<Link> {
+        let outgoing = self.outgoing.get(&id).into_iter().flatten()
+            .map(|&(kind, to)| Link { from: id, kind, to });
+        let incoming = self.incoming.get(&id).into_iter().flatten()
+            .map(|&(kind, from)| Link { from, kind, to: id });
+        outgoing.chain(incoming).collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Link> + '_ {
+        self.outgoing.iter()
+            .flat_map(|(&from, targets)| targets.iter().map(move |&(kind, to)| Link { from, kind, to }))
+    }
+
+    /// The tickets blocking `id`, directly or through other tickets, closest first.
+    pub fn blockers(&self, id: TicketId) -> Vec<TicketId> {
+        let mut blockers = Vec::new();
+        let mut seen = BTreeSet::from([id]);
+        let mut queue = VecDeque::from([id]);
+        while let Some(ticket) = queue.pop_front() {
+            let sources = self.incoming.get(&ticket).into_iter().flatten()
+                .filter(|(kind, _)| *kind == LinkKind::Blocks)
+                .map(|&(_, from)| from);
+            for blocker in sources {
+                if seen.insert(blocker) {
+                    blockers.push(blocker);
+                    queue.push_back(blocker);
+                }
+            }
+        }
+        blockers
+    }
+
+    fn targets(&self, id: TicketId, kind: LinkKind) -> impl Iterator<Item = TicketId> + '_ {
+        self.outgoing.get(&id).into_iter().flatten()
+            .filter(move |(k, _)| *k == kind)
+            .map(|&(_, to)| to)
+    }
+
+    /// Whether `to` can be reached from `from` by following links of `kind`.
+    fn reaches(&self, from: TicketId, kind: LinkKind, to: TicketId) -> bool {
+        let mut seen = BTreeSet::from([from]);
+        let mut stack = vec![from];
+        while let Some(ticket) = stack.pop() {
+            if ticket == to {
+                return true;
+            }
+            stack.extend(self.targets(ticket, kind).filter(|&next| seen.insert(next)));
+        }
+        false
+    }
+}
+
+fn remove_edge(edges: &mut BTreeMap<TicketId, BTreeSet<(LinkKind, TicketId)>>, id: TicketId, edge: (LinkKind, TicketId)) -> bool {
+    let Some(ticket_edges) = edges.get_mut(&id) else {
+        return false;
+    };
+    let removed = ticket_edges.remove(&edge);
+    if ticket_edges.is_empty() {
+        edges.remove(&id);
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link(from: u64, kind: LinkKind, to: u64) -> Link {
+        Link { from: TicketId(from), kind, to: TicketId(to) }
+    }
+
+    fn graph(links: &[Link]) -> LinkGraph {
+        let mut graph = LinkGraph::new();
+        for link in links {
+            graph.check(link).unwrap();
+            graph.insert(*link);
+        }
+        graph
+    }
+
+    #[test]
+    fn test_self_links_are_rejected() {
+        let err = LinkGraph::new().check(&link(1, LinkKind::Duplicates, 1)).unwrap_err();
+        assert!(matches!(err, LinkError::SelfLink));
+    }
+
+    #[test]
+    fn test_blocking_cycles_are_rejected() {
+        let graph = graph(&[link(1, LinkKind::Blocks, 2), link(2, LinkKind::Blocks, 3)]);
+        let closing = link(3, LinkKind::Blocks, 1);
+        assert!(matches!(graph.check(&closing), Err(LinkError::Cycle(link)) if link == closing));
+        assert!(graph.check(&link(1, LinkKind::Blocks, 3)).is_ok());
+    }
+
+    #[test]
+    fn test_parent_chains_cannot_loop() {
+        let graph = graph(&[link(1, LinkKind::SubtaskOf, 2), link(2, LinkKind::SubtaskOf, 3)]);
+        assert!(matches!(graph.check(&link(3, LinkKind::SubtaskOf, 1)), Err(LinkError::Cycle(_))));
+        let err = graph.check(&link(1, LinkKind::SubtaskOf, 3)).unwrap_err();
+        assert!(matches!(err, LinkError::ParentAlreadySet { ticket: TicketId(1), parent: TicketId(2) }));
+    }
+
+    #[test]
+    fn test_duplicates_do_not_count_towards_cycles() {
+        let graph = graph(&[
+            link(1, LinkKind::Blocks, 2),
+            link(2, LinkKind::Duplicates, 3),
+            link(3, LinkKind::Duplicates, 2),
+        ]);
+        // Going back from 3 to 1 only goes through a duplicate, which is not a blocking link.
+        assert!(graph.check(&link(3, LinkKind::Blocks, 1)).is_ok());
+        assert!(matches!(graph.check(&link(2, LinkKind::Blocks, 1)), Err(LinkError::Cycle(_))));
+    }
+
+    #[test]
+    fn test_blockers_are_listed_closest_first() {
+        let graph = graph(&[
+            link(2, LinkKind::Blocks, 1),
+            link(3, LinkKind::Blocks, 1),
+            link(4, LinkKind::Blocks, 2),
+            link(4, LinkKind::Blocks, 3),
+            link(5, LinkKind::Duplicates, 4),
+            link(6, LinkKind::SubtaskOf, 1),
+        ]);
+        let blockers: Vec<_> = graph.blockers(TicketId(1)).into_iter().map(|id| id.0).collect();
+        assert_eq!(blockers, vec![2, 3, 4]);
+        assert!(graph.blockers(TicketId(4)).is_empty());
+    }
+
+    #[test]
+    fn test_removing_a_ticket_removes_its_links() {
+        let mut graph = graph(&[
+            link(1, LinkKind::Blocks, 2),
+            link(2, LinkKind::Blocks, 3),
+            link(3, LinkKind::Duplicates, 1),
+        ]);
+        graph.remove_ticket(TicketId(2));
+        assert_eq!(graph.iter().collect::<Vec<_>>(), vec![link(3, LinkKind::Duplicates, 1)]);
+        assert_eq!(graph.links_of(TicketId(1)), vec![link(3, LinkKind::Duplicates, 1)]);
+        assert!(!graph.outgoing.contains_key(&TicketId(2)) && !graph.incoming.contains_key(&TicketId(2)));
+    }
+
+    #[test]
+    fn test_relations_round_trip() {
+        for relation in Relation::ALL {
+            let link = relation.link(TicketId(1), TicketId(2));
+            assert_eq!(Relation::of(TicketId(1), &link), (relation, TicketId(2)));
+            assert_eq!(relation.to_string().parse::<Relation>().unwrap(), relation);
+        }
+        assert_eq!("Blocked-By".parse::<Relation>().unwrap(), Relation::BlockedBy);
+    }
+}
@@ -1,7 +1,35 @@
-use outro_08::server::{listen, run_server};
+use std::process::ExitCode;
+use std::time::Duration;
+use outro_08::config::Config;
+use outro_08::repository::FileRepository;
+use outro_08::server::{listen_on, run_server_with};
+use outro_08::store::TicketStore;
+
+const SNAPSHOT_PERIOD: Duration = Duration::from_secs(60);
 
 #[tokio::main]
-async fn main() -> std::io::Result<()> {
-    let listener = listen(Some(8080)).await?;
-    run_server(listener).await
+async fn main() -> ExitCode {
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::from(2);
+        }
+    };
+    match run(config).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(config: Config) -> std::io::Result<()> {
+    tide::log::with_level(config.log_level);
+    let store = TicketStore::with_repository(FileRepository::open(&config.storage_path)?)
+        .with_workflow(config.workflow.clone());
+    store.spawn_snapshots(SNAPSHOT_PERIOD);
+    let listener = listen_on(config.socket_address()).await?;
+    run_server_with(listener, store, config.server_settings()?).await
 }
@@ -0,0 +1,172 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tide::{Middleware, Next, Request};
+use crate::data::Status;
+
+/// Upper bounds of the request latency buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 11] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0];
+
+/// Upper bounds of the lock wait buckets, in seconds: waits are usually much shorter than requests.
+const LOCK_WAIT_BUCKETS: [f64; 8] = [0.000_01, 0.000_1, 0.001, 0.005, 0.01, 0.05, 0.1, 1.0];
+
+/// Requests to paths matching none of the routes are counted under this one,
+/// so that scanning for random paths doesn't create a series for each of them.
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+#[derive(Clone, Debug)]
+struct Histogram {
+    bounds: &'static [f64],
+    /// How many observations fell in each bucket, not cumulated.
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Histogram { bounds, counts: vec![0; bounds.len()], sum: 0.0, count: 0 }
+    }
+
+    fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        if let Some(bucket) = self.bounds.iter().position(|&bound| seconds <= bound) {
+            self.counts[bucket] += 1;
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+
+    /// Writes the series of the histogram, `labels` being the ones shared by all of them.
+    fn write(&self, out: &mut String, name: &str, labels: &str) {
+        let mut cumulated = 0;
+        for (bound, count) in self.bounds.iter().zip(&self.counts) {
+            cumulated += count;
+            let _ = writeln!(out, "{}_bucket{{{},le=\"{}\"}} {}", name, labels, bound, cumulated);
+        }
+        let _ = writeln!(out, "{}_bucket{{{},le=\"+Inf\"}} {}", name, labels, self.count);
+        let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, self.sum);
+        let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, self.count);
+    }
+}
+
+/// How the ticket store lock was acquired.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LockMode {
+    Read,
+    Write,
+}
+
+impl LockMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            LockMode::Read => "read",
+            LockMode::Write => "write",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct RequestKey {
+    method: String,
+    route: &'static str,
+    status: u16,
+}
+
+/// What the server reports at `GET /metrics`, in the Prometheus text format.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    requests: Mutex<BTreeMap<RequestKey, Histogram>>,
+    lock_waits: Mutex<BTreeMap<LockMode, Histogram>>,
+}
+
+impl Metrics {
+    pub fn observe_request(&self, method: &str, route: &'static str, status: u16, duration: Duration) {
+        let key = RequestKey { method: method.to_string(), route, status };
+        self.requests.lock().unwrap().entry(key)
+            .or_insert_with(|| Histogram::new(&LATENCY_BUCKETS))
+            .observe(duration);
+    }
+
+    pub fn observe_lock_wait(&self, mode: LockMode, duration: Duration) {
+        self.lock_waits.lock().unwrap().entry(mode)
+            .or_insert_with(|| Histogram::new(&LOCK_WAIT_BUCKETS))
+            .observe(duration);
+    }
+
+    /// Renders every metric, along with the given number of tickets in each status.
+    pub fn render(&self, tickets_by_status: &BTreeMap<Status, usize>) -> String {
+        let mut out = String::new();
+        // Cloned, so that requests are not held up while formatting.
+        let requests = self.requests.lock().unwrap().clone();
+        let lock_waits = self.lock_waits.lock().unwrap().clone();
+
+        out.push_str("# HELP tickets_http_requests_total HTTP requests handled, by route and status code.\n");
+        out.push_str("# TYPE tickets_http_requests_total counter\n");
+        for (key, histogram) in &requests {
+            let _ = writeln!(out, "tickets_http_requests_total{{{}}} {}", key.labels(), histogram.count);
+        }
+
+        out.push_str("# HELP tickets_http_request_duration_seconds Time spent handling HTTP requests, by route and status code.\n");
+        out.push_str("# TYPE tickets_http_request_duration_seconds histogram\n");
+        for (key, histogram) in &requests {
+            histogram.write(&mut out, "tickets_http_request_duration_seconds", &key.labels());
+        }
+
+        out.push_str("# HELP tickets_by_status Tickets currently in each status.\n");
+        out.push_str("# TYPE tickets_by_status gauge\n");
+        for (status, count) in tickets_by_status {
+            let _ = writeln!(out, "tickets_by_status{{status=\"{}\"}} {}", status, count);
+        }
+
+        out.push_str("# HELP tickets_store_lock_wait_seconds Time spent waiting for the ticket store lock.\n");
+        out.push_str("# TYPE tickets_store_lock_wait_seconds histogram\n");
+        for (mode, histogram) in &lock_waits {
+            let labels = format!("mode=\"{}\"", mode.as_str());
+            histogram.write(&mut out, "tickets_store_lock_wait_seconds", &labels);
+        }
+        out
+    }
+}
+
+impl RequestKey {
+    fn labels(&self) -> String {
+        format!("method=\"{}\",route=\"{}\",status=\"{}\"", self.method, self.route, self.status)
+    }
+}
+
+/// The route among `routes` matching `path`, where `:`-prefixed segments match any segment.
+fn route_of(routes: &[&'static str], path: &str) -> &'static str {
+    let segments: Vec<_> = path.trim_end_matches('/').split('/').collect();
+    routes.iter().copied()
+        .find(|route| {
+            let pattern: Vec<_> = route.split('/').collect();
+            pattern.len() == segments.len()
+                && pattern.iter().zip(&segments).all(|(expected, actual)| expected.starts_with(':') || expected == actual)
+        })
+        .unwrap_or(UNMATCHED_ROUTE)
+}
+
+/// Counts and times every request, by route and status code.
+///
+/// Must come before the error handler, so that it sees the status codes
+/// errors are turned into.
+#[derive(Clone, Debug)]
+pub struct RecordMetrics {
+    pub metrics: Arc<Metrics>,
+    /// The routes of the server, used as labels instead of paths.
+    pub routes: Arc<[&'static str]>,
+}
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for RecordMetrics {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let start = Instant::now();
+        let method = req.method().to_string();
+        let route = route_of(&self.routes, req.url().path());
+        let response = next.run(req).await;
+        self.metrics.observe_request(&method, route, response.status().into(), start.elapsed());
+        Ok(response)
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant};
+use futures::io::BufReader;
+use futures::AsyncRead;
+use tide::{Body, Middleware, Next, Request};
+use crate::auth::{TokenId, User};
+use crate::server::MyError;
+
+/// Rejects requests with a body larger than the given number of bytes.
+///
+/// Bodies announcing their length are rejected before being read. Others,
+/// like chunked ones, are cut off once they get too large: the handler sees
+/// a read error, and the client a `413 Payload Too Large`.
+#[derive(Clone, Copy, Debug)]
+pub struct BodyLimit(pub usize);
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for BodyLimit {
+    async fn handle(&self, mut req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        match req.len() {
+            Some(len) if len > self.0 => return Err(MyError::PayloadTooLarge(self.0).into()),
+            Some(_) => return Ok(next.run(req).await),
+            None => {}
+        }
+        let exceeded = Arc::new(AtomicBool::new(false));
+        let body = LimitedReader { inner: req.take_body(), remaining: self.0, limit: self.0, exceeded: exceeded.clone() };
+        req.set_body(Body::from_reader(BufReader::new(body), None));
+        let response = next.run(req).await;
+        if exceeded.load(Ordering::Relaxed) {
+            return Err(MyError::PayloadTooLarge(self.0).into());
+        }
+        Ok(response)
+    }
+}
+
+/// Fails once more than `limit` bytes are read, and flags it in `exceeded`.
+struct LimitedReader<R> {
+    inner: R,
+    remaining: usize,
+    limit: usize,
+    exceeded: Arc<AtomicBool>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for LimitedReader<R> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        // One more byte than allowed is enough to tell the body is too large.
+        let max = buf.len().min(self.remaining + 1);
+        let read = ready!(Pin::new(&mut self.inner).poll_read(cx, &mut buf[..max]))?;
+        if read > self.remaining {
+            self.exceeded.store(true, Ordering::Relaxed);
+            let message = format!("Request body is larger than {} bytes", self.limit);
+            return Poll::Ready(Err(io::Error::new(io::ErrorKind::InvalidData, message)));
+        }
+        self.remaining -= read;
+        Poll::Ready(Ok(read))
+    }
+}
+
+/// How many requests each client can make: `burst` at once, then `per_second`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    pub per_second: u32,
+    pub burst: u32,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        RateLimit { per_second: 10, burst: 100 }
+    }
+}
+
+/// How many clients are tracked before the least recently seen ones are forgotten.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// Who requests are counted against: the token they were authenticated with,
+/// or the IP address they come from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Client {
+    Token(TokenId),
+    Address(IpAddr),
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+#[derive(Debug, Default)]
+struct Buckets {
+    buckets: BTreeMap<Client, Bucket>,
+    /// The same clients, by the last time they made a request.
+    recency: BTreeSet<(Instant, Client)>,
+}
+
+/// Which requests a `RateLimiter` counts, and against whom.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Counted {
+    /// Every request, by address.
+    Requests,
+    /// Requests failing authentication, by address.
+    FailedAuthentications,
+    /// Authenticated requests, by token.
+    Tokens,
+}
+
+/// Rejects requests with `429 Too Many Requests` once a client exceeds its `RateLimit`,
+/// using a token bucket per client.
+///
+/// With authentication, one limiter comes before `Authenticate`, so that clients can't
+/// keep on guessing tokens, and another one after it, counting requests by token.
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    limit: RateLimit,
+    counted: Counted,
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+impl RateLimiter {
+    /// Counts every request, by the IP address it comes from.
+    pub fn by_address(limit: RateLimit) -> Self {
+        RateLimiter { limit, counted: Counted::Requests, buckets: Arc::default() }
+    }
+
+    /// Counts requests failing authentication, by the IP address they come from, and
+    /// turns away addresses that failed too often before their token is checked.
+    /// Users sharing an address are then only limited by their own token.
+    pub fn by_failed_authentication(limit: RateLimit) -> Self {
+        RateLimiter { limit, counted: Counted::FailedAuthentications, buckets: Arc::default() }
+    }
+
+    /// Counts requests by the token they were authenticated with, letting others through.
+    pub fn by_token(limit: RateLimit) -> Self {
+        RateLimiter { limit, counted: Counted::Tokens, buckets: Arc::default() }
+    }
+
+    /// Takes a token from the bucket of `client`, or tells how long until there is one.
+    /// Only checks there is one unless `take`.
+    fn acquire(&self, client: Client, now: Instant, take: bool) -> Result<(), Duration> {
+        let capacity = f64::from(self.limit.burst);
+        let rate = f64::from(self.limit.per_second);
+        let mut buckets = self.buckets.lock().unwrap();
+        let Buckets { buckets, recency } = &mut *buckets;
+        let bucket = match buckets.get_mut(&client) {
+            Some(bucket) => {
+                recency.remove(&(bucket.updated, client));
+                bucket
+            }
+            None => {
+                if buckets.len() >= MAX_TRACKED_CLIENTS {
+                    if let Some((_, idle)) = recency.pop_first() {
+                        buckets.remove(&idle);
+                    }
+                }
+                buckets.entry(client).or_insert(Bucket { tokens: capacity, updated: now })
+            }
+        };
+        bucket.tokens = (bucket.tokens + rate * (now - bucket.updated).as_secs_f64()).min(capacity);
+        bucket.updated = now;
+        recency.insert((now, client));
+        if bucket.tokens >= 1.0 {
+            if take {
+                bucket.tokens -= 1.0;
+            }
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        }
+    }
+}
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for RateLimiter {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let client = match self.counted {
+            Counted::Tokens => req.ext::<User>().map(|user| Client::Token(user.token)),
+            Counted::Requests | Counted::FailedAuthentications => req.peer_addr()
+                .and_then(|addr| addr.parse::<SocketAddr>().ok())
+                .map(|addr| Client::Address(addr.ip())),
+        };
+        let Some(client) = client else {
+            return Ok(next.run(req).await);
+        };
+        let take = self.counted != Counted::FailedAuthentications;
+        self.acquire(client, Instant::now(), take).map_err(MyError::TooManyRequests)?;
+        let response = next.run(req).await;
+        if !take && matches!(response.downcast_error::<MyError>(), Some(MyError::Unauthorized(_))) {
+            // Running out of tokens only matters to the next request.
+            let _ = self.acquire(client, Instant::now(), true);
+        }
+        Ok(response)
+    }
+}
@@ -0,0 +1,143 @@
+use std::borrow::Cow;
+use ticket_fields::{TicketAssignee, TicketComment, TicketDescription, TicketLabel, TicketTitle};
+use utoipa::openapi::schema::{ObjectBuilder, Schema, Type};
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::openapi::RefOr;
+use utoipa::{Modify, OpenApi, PartialSchema, ToSchema};
+use crate::server::{self, TicketCommentSerializer, TicketDescriptionSerializer, TicketTitleSerializer};
+use crate::{bulk, websocket};
+
+/// The OpenAPI document of the REST API, served at `GET /openapi.json`.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Ticket management system"),
+    paths(
+        server::new_ticket,
+        server::list_tickets,
+        server::search_tickets,
+        bulk::import_tickets,
+        bulk::export_tickets,
+        server::get_ticket,
+        server::patch_ticket,
+        server::delete_ticket,
+        server::ticket_history,
+        server::link_ticket,
+        server::list_links,
+        server::unlink_ticket,
+        server::ticket_blockers,
+        server::new_comment,
+        server::list_comments,
+        server::ticket_events,
+        websocket::ticket_subscriptions,
+    ),
+    modifiers(&BearerAuth),
+    security((), ("bearer" = [])),
+)]
+pub struct ApiDoc;
+
+/// Tokens are only required if the server is configured to, see `ServerSettings::tokens`.
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let scheme = HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build();
+        openapi.components.get_or_insert_with(Default::default)
+            .add_security_scheme("bearer", SecurityScheme::Http(scheme));
+    }
+}
+
+// The validation rules of `ticket_fields` can't be derived,
+// so they are spelled out here from the same constants.
+
+impl PartialSchema for TicketTitleSerializer {
+    fn schema() -> RefOr<Schema> {
+        ObjectBuilder::new()
+            .schema_type(Type::String)
+            .min_length(Some(1))
+            .max_length(Some(TicketTitle::MAX_LENGTH))
+            .description(Some("Non-empty, measured in bytes"))
+            .into()
+    }
+}
+
+impl ToSchema for TicketTitleSerializer {
+    fn name() -> Cow<'static, str> {
+        Cow::Borrowed("TicketTitle")
+    }
+}
+
+impl PartialSchema for TicketDescriptionSerializer {
+    fn schema() -> RefOr<Schema> {
+        ObjectBuilder::new()
+            .schema_type(Type::String)
+            .min_length(Some(1))
+            .max_length(Some(TicketDescription::MAX_LENGTH))
+            .description(Some("Non-empty, measured in bytes"))
+            .into()
+    }
+}
+
+impl ToSchema for TicketDescriptionSerializer {
+    fn name() -> Cow<'static, str> {
+        Cow::Borrowed("TicketDescription")
+    }
+}
+
+impl PartialSchema for TicketCommentSerializer {
+    fn schema() -> RefOr<Schema> {
+        ObjectBuilder::new()
+            .schema_type(Type::String)
+            .min_length(Some(1))
+            .max_length(Some(TicketComment::MAX_LENGTH))
+            .description(Some("Non-empty, measured in bytes"))
+            .into()
+    }
+}
+
+impl ToSchema for TicketCommentSerializer {
+    fn name() -> Cow<'static, str> {
+        Cow::Borrowed("TicketComment")
+    }
+}
+
+/// The OpenAPI schema of `TicketAssignee`.
+pub struct TicketAssigneeSchema;
+
+impl PartialSchema for TicketAssigneeSchema {
+    fn schema() -> RefOr<Schema> {
+        ObjectBuilder::new()
+            .schema_type(Type::String)
+            .min_length(Some(1))
+            .max_length(Some(TicketAssignee::MAX_LENGTH))
+            .pattern(Some(r"^[\p{L}\p{N}.@_-]+$"))
+            .description(Some("A user name, measured in bytes"))
+            .into()
+    }
+}
+
+impl ToSchema for TicketAssigneeSchema {
+    fn name() -> Cow<'static, str> {
+        Cow::Borrowed("TicketAssignee")
+    }
+}
+
+/// The OpenAPI schema of `TicketLabel`.
+pub struct TicketLabelSchema;
+
+impl PartialSchema for TicketLabelSchema {
+    fn schema() -> RefOr<Schema> {
+        ObjectBuilder::new()
+            .schema_type(Type::String)
+            .min_length(Some(1))
+            .max_length(Some(TicketLabel::MAX_LENGTH))
+            .pattern(Some(r"^[\p{Ll}\p{N}:/_-]+$"))
+            .description(Some("Measured in bytes"))
+            .into()
+    }
+}
+
+impl ToSchema for TicketLabelSchema {
+    fn name() -> Cow<'static, str> {
+        Cow::Borrowed("TicketLabel")
+    }
+}
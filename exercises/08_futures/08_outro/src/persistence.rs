@@ -0,0 +1,453 @@
+//! Durable storage for the tickets, on the local file system.
+//!
+//! Writes are synchronous: a mutation is written and synced while the store
+//! lock is held, so that changes reach the disk in the order they are applied
+//! and are durable before anyone can see them. The price is that every write
+//! waits for the disk. On a multi-threaded runtime, the other tasks of the
+//! worker are handed over to other workers in the meantime, but requests that
+//! need the store lock wait for the sync to complete.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::runtime::{Handle, RuntimeFlavor};
+use crate::data::{Comment, Ticket};
+use crate::history::HistoryEntry;
+use crate::links::Link;
+use crate::server::{CommentSerializer, TicketSerializer};
+use crate::store::TicketId;
+
+const WAL_FILE: &str = "wal.jsonl";
+const SNAPSHOT_FILE: &str = "snapshot.json";
+const HISTORY_FILE: &str = "history.jsonl";
+
+/// A single mutation, as it is written to the write-ahead log.
+///
+/// Changes to tickets carry their history entries, so that a crash
+/// can't keep one without the other.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Record {
+    Created {
+        #[serde(with = "TicketSerializer")]
+        ticket: Ticket,
+        entry: HistoryEntry,
+    },
+    /// Tickets created at once, in a single line so that a crash can't leave only some of them.
+    Imported { tickets: Vec<StoredTicket>, entries: Vec<HistoryEntry> },
+    Updated {
+        #[serde(with = "TicketSerializer")]
+        ticket: Ticket,
+        entry: HistoryEntry,
+    },
+    /// Also removes the comments and links of the ticket.
+    Removed { id: TicketId, entry: HistoryEntry },
+    Commented(#[serde(with = "CommentSerializer")] Comment),
+    Linked(Link),
+    Unlinked(Link),
+}
+
+impl Record {
+    /// The history entries written along with the mutation.
+    pub fn history(&self) -> &[HistoryEntry] {
+        match self {
+            Record::Created { entry, .. } | Record::Updated { entry, .. } | Record::Removed { entry, .. } => {
+                std::slice::from_ref(entry)
+            }
+            Record::Imported { entries, .. } => entries,
+            Record::Commented(_) | Record::Linked(_) | Record::Unlinked(_) => &[],
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StoredTicket(#[serde(with = "TicketSerializer")] pub Ticket);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StoredComment(#[serde(with = "CommentSerializer")] pub Comment);
+
+/// The full content of a store at a given point in time.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub counter: u64,
+    pub tickets: Vec<StoredTicket>,
+    /// Grouped by ticket, in id order within each ticket.
+    #[serde(default)]
+    pub comments: Vec<StoredComment>,
+    #[serde(default)]
+    pub links: Vec<Link>,
+}
+
+/// The content of a snapshot, indexed so that the log can be replayed on top of it.
+struct Replay {
+    counter: u64,
+    tickets: BTreeMap<TicketId, Ticket>,
+    comments: BTreeMap<TicketId, Vec<Comment>>,
+    links: BTreeSet<Link>,
+}
+
+impl From<Snapshot> for Replay {
+    fn from(snapshot: Snapshot) -> Self {
+        let mut comments: BTreeMap<TicketId, Vec<Comment>> = BTreeMap::new();
+        for StoredComment(comment) in snapshot.comments {
+            comments.entry(comment.ticket_id).or_default().push(comment);
+        }
+        Replay {
+            counter: snapshot.counter,
+            tickets: snapshot.tickets.into_iter().map(|t| (t.0.id, t.0)).collect(),
+            comments,
+            links: snapshot.links.into_iter().collect(),
+        }
+    }
+}
+
+impl From<Replay> for Snapshot {
+    fn from(replay: Replay) -> Self {
+        Snapshot {
+            counter: replay.counter,
+            tickets: replay.tickets.into_values().map(StoredTicket).collect(),
+            comments: replay.comments.into_values().flatten().map(StoredComment).collect(),
+            links: replay.links.into_iter().collect(),
+        }
+    }
+}
+
+impl Replay {
+    fn insert(&mut self, ticket: Ticket) {
+        self.counter = self.counter.max(ticket.id.0 + 1);
+        self.tickets.insert(ticket.id, ticket);
+    }
+
+    // Replaying a record on top of a snapshot that already has it is harmless.
+    fn apply(&mut self, record: Record) {
+        match record {
+            Record::Created { ticket, .. } | Record::Updated { ticket, .. } => self.insert(ticket),
+            Record::Imported { tickets, .. } => {
+                for StoredTicket(ticket) in tickets {
+                    self.insert(ticket);
+                }
+            }
+            Record::Removed { id, .. } => {
+                self.tickets.remove(&id);
+                self.comments.remove(&id);
+                self.links.retain(|link| !link.involves(id));
+            }
+            Record::Commented(comment) => {
+                let ticket_comments = self.comments.entry(comment.ticket_id).or_default();
+                if ticket_comments.last().is_none_or(|last| last.id < comment.id) {
+                    ticket_comments.push(comment);
+                }
+            }
+            Record::Linked(link) => {
+                self.links.insert(link);
+            }
+            Record::Unlinked(link) => {
+                self.links.remove(&link);
+            }
+        }
+    }
+}
+
+/// Stores a JSON snapshot plus a JSON Lines write-ahead log in a directory.
+///
+/// Every mutation is appended before it becomes visible in memory,
+/// and from time to time the whole store is compacted into a snapshot.
+/// The history entries of the log are then archived to a JSON Lines file,
+/// which is never compacted.
+pub struct FileStorage {
+    dir: PathBuf,
+    wal: Mutex<File>,
+    history: Mutex<File>,
+    /// The history entries in the log that are not archived yet.
+    /// Always locked after `wal`.
+    pending: Mutex<Vec<HistoryEntry>>,
+}
+
+impl FileStorage {
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let open = |name| OpenOptions::new().create(true).read(true).append(true).open(dir.join(name));
+        let wal = open(WAL_FILE)?;
+        let history = open(HISTORY_FILE)?;
+        Ok(Self { dir, wal: Mutex::new(wal), history: Mutex::new(history), pending: Mutex::default() })
+    }
+
+    fn read_snapshot(&self) -> io::Result<Snapshot> {
+        match File::open(self.dir.join(SNAPSHOT_FILE)) {
+            Ok(file) => Ok(serde_json::from_reader(BufReader::new(file))?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Snapshot::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Rebuilds the latest persisted state, along with every history entry recorded so far.
+    pub fn recover(&self) -> io::Result<(Snapshot, Vec<HistoryEntry>)> {
+        let mut replay = Replay::from(self.read_snapshot()?);
+        let wal = self.wal.lock().unwrap();
+        let mut logged = Vec::new();
+        for record in read_lines::<Record>(&wal, "write-ahead log")? {
+            logged.extend_from_slice(record.history());
+            replay.apply(record);
+        }
+        let mut history: Vec<HistoryEntry> = read_lines(&self.history.lock().unwrap(), "history")?;
+        // A snapshot that failed after archiving the history of the log leaves it in both.
+        let archived = (0..=logged.len()).rev()
+            .find(|&len| history.ends_with(&logged[..len]))
+            .unwrap_or(0);
+        logged.drain(..archived);
+        history.extend(logged.iter().cloned());
+        *self.pending.lock().unwrap() = logged;
+        Ok((replay.into(), history))
+    }
+
+    /// Durably records a single mutation, along with its history entries.
+    pub fn append(&self, record: &Record) -> io::Result<()> {
+        blocking(|| {
+            let mut wal = self.wal.lock().unwrap();
+            append_lines(&mut wal, std::slice::from_ref(record))?;
+            self.pending.lock().unwrap().extend_from_slice(record.history());
+            Ok(())
+        })
+    }
+
+    /// Replaces everything persisted so far with `snapshot`,
+    /// once the history entries of the log are archived.
+    pub fn snapshot(&self, snapshot: &Snapshot) -> io::Result<()> {
+        blocking(|| self.write_snapshot(snapshot))
+    }
+
+    fn write_snapshot(&self, snapshot: &Snapshot) -> io::Result<()> {
+        let wal = self.wal.lock().unwrap();
+        let mut pending = self.pending.lock().unwrap();
+        // If anything below fails, the log is kept and still holds the
+        // archived entries: recovering skips them.
+        append_lines(&mut self.history.lock().unwrap(), &pending)?;
+        pending.clear();
+        let tmp_path = self.dir.join(format!("{}.tmp", SNAPSHOT_FILE));
+        let mut tmp = File::create(&tmp_path)?;
+        serde_json::to_writer(&mut tmp, snapshot)?;
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, self.dir.join(SNAPSHOT_FILE))?;
+        // Replaying the log on top of the new snapshot is harmless,
+        // so a crash right here does not lose or corrupt anything.
+        wal.set_len(0)?;
+        wal.sync_data()
+    }
+}
+
+/// Runs blocking file I/O, handing the other tasks of the current runtime worker
+/// over to other workers if there are any.
+fn blocking<T>(f: impl FnOnce() -> T) -> T {
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => tokio::task::block_in_place(f),
+        _ => f(),
+    }
+}
+
+/// Reads a JSON Lines file.
+///
+/// Only lines terminated by a newline were completely written.
+/// A broken line is tolerated only at the very end of the file,
+/// where a crash in the middle of an append would leave it.
+fn read_lines<T: DeserializeOwned>(mut file: &File, name: &str) -> io::Result<Vec<T>> {
+    let mut content = Vec::new();
+    file.read_to_end(&mut content)?;
+
+    let mut items = Vec::new();
+    let mut valid_len = 0;
+    let mut lines = content.split_inclusive(|b| *b == b'\n').peekable();
+    while let Some(line) = lines.next() {
+        let is_last = lines.peek().is_none();
+        let item = match serde_json::from_slice::<T>(line) {
+            Ok(item) if line.ends_with(b"\n") => item,
+            _ if is_last => break,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Corrupted {} record at byte {}", name, valid_len),
+                ));
+            }
+        };
+        items.push(item);
+        valid_len += line.len();
+    }
+    if valid_len < content.len() {
+        file.set_len(valid_len as u64)?;
+        file.sync_data()?;
+    }
+    Ok(items)
+}
+
+/// Appends `items` with a single write, then truncates whatever got written if it fails,
+/// so that later appends don't follow a broken line.
+fn append_lines<T: Serialize>(file: &mut File, items: &[T]) -> io::Result<()> {
+    if items.is_empty() {
+        return Ok(());
+    }
+    let mut lines = Vec::new();
+    for item in items {
+        serde_json::to_writer(&mut lines, item)?;
+        lines.push(b'\n');
+    }
+    let len = file.metadata()?.len();
+    match file.write_all(&lines).and_then(|_| file.sync_data()) {
+        Ok(()) => Ok(()),
+        Err(e) => match file.set_len(len).and_then(|_| file.sync_data()) {
+            Ok(()) => Err(e),
+            Err(rollback) => Err(io::Error::new(
+                e.kind(),
+                format!("{}, and could not be rolled back: {}", e, rollback),
+            )),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{Priority, Status};
+    use crate::links::LinkKind;
+    use crate::store::CommentId;
+
+    fn ticket(id: u64) -> Ticket {
+        Ticket {
+            id: TicketId(id),
+            title: format!("Title {}", id).try_into().unwrap(),
+            description: format!("Description {}", id).try_into().unwrap(),
+            status: Status::ToDo,
+            priority: Priority::default(),
+            assignee: None,
+            labels: BTreeSet::new(),
+            version: 1,
+        }
+    }
+
+    fn create(storage: &FileStorage, id: u64) -> HistoryEntry {
+        let ticket = ticket(id);
+        let entry = HistoryEntry::created(&ticket);
+        storage.append(&Record::Created { ticket, entry: entry.clone() }).unwrap();
+        entry
+    }
+
+    fn snapshot_of(ids: &[u64]) -> Snapshot {
+        Snapshot {
+            counter: ids.iter().max().map_or(0, |id| id + 1),
+            tickets: ids.iter().map(|&id| StoredTicket(ticket(id))).collect(),
+            ..Snapshot::default()
+        }
+    }
+
+    #[test]
+    fn test_torn_last_line_is_truncated() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = FileStorage::open(&dir).unwrap();
+        create(&storage, 0);
+        let path = dir.path().join(WAL_FILE);
+        let len = fs::metadata(&path).unwrap().len();
+        // Valid JSON, but without its newline it may be missing the end of the record.
+        let mut wal = OpenOptions::new().append(true).open(&path).unwrap();
+        wal.write_all(br#"{"Linked":{"from":0,"kind":"blocks","to":1}}"#).unwrap();
+        drop((wal, storage));
+
+        let storage = FileStorage::open(&dir).unwrap();
+        let (snapshot, _) = storage.recover().unwrap();
+        assert_eq!((snapshot.tickets.len(), snapshot.links.len()), (1, 0));
+        assert_eq!(fs::metadata(&path).unwrap().len(), len);
+        // Later records are appended after the last complete one.
+        create(&storage, 1);
+        drop(storage);
+        let (snapshot, history) = FileStorage::open(&dir).unwrap().recover().unwrap();
+        assert_eq!((snapshot.tickets.len(), history.len()), (2, 2));
+    }
+
+    #[test]
+    fn test_broken_line_before_the_end_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = FileStorage::open(&dir).unwrap();
+        create(&storage, 0);
+        let mut wal = OpenOptions::new().append(true).open(dir.path().join(WAL_FILE)).unwrap();
+        wal.write_all(b"{\"Linked\":\n").unwrap();
+        drop(wal);
+        create(&storage, 1);
+        drop(storage);
+
+        let err = FileStorage::open(&dir).unwrap().recover().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_replaying_a_log_on_top_of_its_snapshot_is_harmless() {
+        let mut replay = Replay::from(Snapshot::default());
+        let comment = Comment {
+            id: CommentId(0),
+            ticket_id: TicketId(0),
+            author: "jane".try_into().unwrap(),
+            body: "Hello".try_into().unwrap(),
+            created_at: crate::data::now(),
+        };
+        let records = [
+            Record::Created { ticket: ticket(0), entry: HistoryEntry::created(&ticket(0)) },
+            Record::Created { ticket: ticket(1), entry: HistoryEntry::created(&ticket(1)) },
+            Record::Commented(comment),
+            Record::Linked(Link { from: TicketId(0), kind: LinkKind::Blocks, to: TicketId(1) }),
+            Record::Removed { id: TicketId(1), entry: HistoryEntry::deleted(&ticket(1)) },
+        ];
+        for record in records.clone() {
+            replay.apply(record);
+        }
+        let once = serde_json::to_value(Snapshot::from(replay)).unwrap();
+
+        let mut replay = Replay::from(serde_json::from_value::<Snapshot>(once.clone()).unwrap());
+        for record in records {
+            replay.apply(record);
+        }
+        assert_eq!(serde_json::to_value(Snapshot::from(replay)).unwrap(), once);
+        assert_eq!(once["counter"], 2);
+        assert_eq!(once["comments"].as_array().unwrap().len(), 1);
+        assert!(once["links"].as_array().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_failing_history_write_keeps_the_change_with_its_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = FileStorage::open(&dir).unwrap();
+        let entry = create(&storage, 0);
+        // As if the disk got full: writing to a read-only handle fails.
+        *storage.history.lock().unwrap() = File::open(dir.path().join(HISTORY_FILE)).unwrap();
+        assert!(storage.snapshot(&snapshot_of(&[0])).is_err());
+        drop(storage);
+
+        let (snapshot, history) = FileStorage::open(&dir).unwrap().recover().unwrap();
+        assert_eq!(snapshot.tickets.len(), 1);
+        assert_eq!(history, vec![entry]);
+    }
+
+    #[test]
+    fn test_entries_archived_by_a_failed_snapshot_are_not_duplicated() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = FileStorage::open(&dir).unwrap();
+        let first = create(&storage, 0);
+        // The history gets archived, but the snapshot itself can't be written.
+        let tmp_path = dir.path().join(format!("{}.tmp", SNAPSHOT_FILE));
+        fs::create_dir(&tmp_path).unwrap();
+        assert!(storage.snapshot(&snapshot_of(&[0])).is_err());
+        drop(storage);
+
+        let storage = FileStorage::open(&dir).unwrap();
+        let (_, history) = storage.recover().unwrap();
+        assert_eq!(history, vec![first.clone()]);
+
+        let second = create(&storage, 1);
+        fs::remove_dir(&tmp_path).unwrap();
+        storage.snapshot(&snapshot_of(&[0, 1])).unwrap();
+        drop(storage);
+
+        let (snapshot, history) = FileStorage::open(&dir).unwrap().recover().unwrap();
+        assert_eq!(snapshot.tickets.len(), 2);
+        assert_eq!(history, vec![first, second]);
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use utoipa::ToSchema;
+
+/// What a user is trusted with. Each role can do everything the previous ones can.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Viewer,
+    Reporter,
+    Maintainer,
+    Admin,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Role::Viewer => "viewer",
+            Role::Reporter => "reporter",
+            Role::Maintainer => "maintainer",
+            Role::Admin => "admin",
+        })
+    }
+}
+
+/// What a request does to tickets, as far as permissions are concerned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// Read tickets, their history, comments and links, or subscribe to their changes.
+    Read,
+    Create,
+    Comment,
+    /// Change any field but the status.
+    Patch,
+    ChangeStatus,
+    /// Add or remove links between tickets.
+    Link,
+    Delete,
+    Import,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Action::Read => "read tickets",
+            Action::Create => "create tickets",
+            Action::Comment => "comment on tickets",
+            Action::Patch => "edit tickets",
+            Action::ChangeStatus => "change the status of tickets",
+            Action::Link => "link tickets",
+            Action::Delete => "delete tickets",
+            Action::Import => "import tickets",
+        })
+    }
+}
+
+/// Why a request was rejected, as sent to clients along with the error.
+#[derive(Error, Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[error("{user} has the {role} role, but only users with the {required_role} role or above may {action}")]
+pub struct Forbidden {
+    pub user: String,
+    pub role: Role,
+    pub action: Action,
+    pub required_role: Role,
+}
+
+/// The roles of users, and the role each action requires.
+///
+/// Only enforced when requests are authenticated. In configuration files:
+///
+/// ```toml
+/// [policy]
+/// default_role = "viewer"
+///
+/// [policy.users]
+/// ada = "admin"
+///
+/// [policy.permissions]
+/// delete = "maintainer"
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    /// The role of users that are not listed in `users`.
+    pub default_role: Role,
+    pub users: BTreeMap<String, Role>,
+    /// Overrides the role required by some actions, see `Policy::default`.
+    pub permissions: BTreeMap<Action, Role>,
+}
+
+impl Default for Policy {
+    /// Everyone can read and is a reporter unless stated otherwise: reporters create
+    /// and comment, maintainers edit, link and move tickets along, admins
+    /// delete and import.
+    fn default() -> Self {
+        Policy { default_role: Role::Reporter, users: BTreeMap::new(), permissions: BTreeMap::new() }
+    }
+}
+
+impl Policy {
+    pub fn with_role(mut self, user: &str, role: Role) -> Self {
+        self.users.insert(user.to_string(), role);
+        self
+    }
+
+    /// Requires at least `role` to perform `action`.
+    pub fn require(mut self, action: Action, role: Role) -> Self {
+        self.permissions.insert(action, role);
+        self
+    }
+
+    pub fn role(&self, user: &str) -> Role {
+        self.users.get(user).copied().unwrap_or(self.default_role)
+    }
+
+    pub fn required_role(&self, action: Action) -> Role {
+        self.permissions.get(&action).copied().unwrap_or(match action {
+            Action::Read => Role::Viewer,
+            Action::Create | Action::Comment => Role::Reporter,
+            Action::Patch | Action::ChangeStatus | Action::Link => Role::Maintainer,
+            Action::Delete | Action::Import => Role::Admin,
+        })
+    }
+
+    pub fn authorize(&self, user: &str, action: Action) -> Result<(), Forbidden> {
+        let role = self.role(user);
+        let required_role = self.required_role(action);
+        if role >= required_role {
+            Ok(())
+        } else {
+            Err(Forbidden { user: user.to_string(), role, action, required_role })
+        }
+    }
+}
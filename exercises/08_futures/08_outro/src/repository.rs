@@ -0,0 +1,330 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::ops::Bound::{Excluded, Unbounded};
+use std::path::Path;
+use std::time::SystemTime;
+use std::sync::{Arc, Mutex};
+use tokio::sync::RwLock;
+use crate::data::{Comment, CommentDraft, Ticket};
+use crate::history::HistoryEntry;
+use crate::links::{Link, LinkError, LinkGraph};
+use crate::persistence::{FileStorage, Record, Snapshot, StoredComment, StoredTicket};
+use crate::store::{CommentId, TicketId};
+
+/// The container behind a `TicketStore`.
+///
+/// Tickets are handed out as `Arc<RwLock<Ticket>>`, so that they can be
+/// modified in place while other tickets are being read or written.
+/// Callers must hold the write lock of a ticket when calling `update` on it.
+pub trait TicketRepository: Send + Sync + 'static {
+    fn get(&self, id: TicketId) -> Option<Arc<RwLock<Ticket>>>;
+
+    /// The id the next inserted ticket should get. Ids are never reused.
+    fn next_id(&self) -> TicketId;
+
+    /// Every change to a ticket comes with its history entry: both are kept, or neither.
+    /// Histories are kept after their ticket is deleted.
+    fn insert(&mut self, ticket: Ticket, entry: HistoryEntry) -> io::Result<Arc<RwLock<Ticket>>>;
+
+    /// Inserts all the tickets, or none of them.
+    fn insert_all(&mut self, tickets: Vec<Ticket>, entries: Vec<HistoryEntry>) -> io::Result<()>;
+
+    /// Records the new content of a ticket, right before it is written to its lock.
+    fn update(&self, ticket: &Ticket, entry: HistoryEntry) -> io::Result<()>;
+
+    /// Also deletes the comments and links of the ticket.
+    fn delete(&mut self, id: TicketId, entry: HistoryEntry) -> io::Result<Option<Arc<RwLock<Ticket>>>>;
+
+    /// All the tickets with an id greater than `after`, in id order.
+    fn scan(&self, after: Option<TicketId>) -> Box<dyn Iterator<Item = Arc<RwLock<Ticket>>> + Send + '_>;
+
+    /// Adds a comment to an existing ticket, numbered after its previous comments.
+    fn add_comment(&self, ticket_id: TicketId, draft: CommentDraft, created_at: SystemTime) -> io::Result<Comment>;
+
+    /// Up to `limit` comments of a ticket with an id greater than `after`, in id order.
+    fn comments(&self, ticket_id: TicketId, after: Option<CommentId>, limit: usize) -> Vec<Comment>;
+
+    /// Adds a link between two existing tickets, if the links so far allow it.
+    /// Returns whether it is new.
+    fn add_link(&self, link: Link) -> Result<bool, LinkError>;
+
+    /// Returns whether the link was there.
+    fn remove_link(&self, link: Link) -> io::Result<bool>;
+
+    /// The links a ticket is the source or the target of.
+    fn links_of(&self, id: TicketId) -> Vec<Link>;
+
+    /// The tickets blocking a ticket, directly or not, closest first.
+    fn blockers(&self, id: TicketId) -> Vec<TicketId>;
+
+    /// The history of a ticket, oldest entry first.
+    fn history(&self, id: TicketId) -> Vec<HistoryEntry>;
+
+    /// Compacts whatever was persisted so far into `tickets`.
+    fn snapshot(&mut self, _tickets: Vec<Ticket>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct InMemoryRepository {
+    tickets: BTreeMap<TicketId, Arc<RwLock<Ticket>>>,
+    counter: u64,
+    comments: Mutex<BTreeMap<TicketId, Vec<Comment>>>,
+    links: Mutex<LinkGraph>,
+    history: Mutex<BTreeMap<TicketId, Vec<HistoryEntry>>>,
+}
+
+impl InMemoryRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn from_snapshot(snapshot: Snapshot, history: Vec<HistoryEntry>) -> Self {
+        let tickets = snapshot.tickets.into_iter()
+            .map(|t| (t.0.id, Arc::new(RwLock::new(t.0))))
+            .collect();
+        let mut comments: BTreeMap<TicketId, Vec<Comment>> = BTreeMap::new();
+        for StoredComment(comment) in snapshot.comments {
+            comments.entry(comment.ticket_id).or_default().push(comment);
+        }
+        let mut links = LinkGraph::new();
+        for link in snapshot.links {
+            links.insert(link);
+        }
+        let repository = Self {
+            tickets,
+            counter: snapshot.counter,
+            comments: Mutex::new(comments),
+            links: Mutex::new(links),
+            history: Mutex::default(),
+        };
+        repository.record(history);
+        repository
+    }
+
+    fn record(&self, entries: impl IntoIterator<Item = HistoryEntry>) {
+        let mut history = self.history.lock().unwrap();
+        for entry in entries {
+            history.entry(entry.ticket_id).or_default().push(entry);
+        }
+    }
+
+    /// Checks and adds a link, keeping it only if `persist` succeeds.
+    fn insert_link(&self, link: Link, persist: impl FnOnce(&Link) -> io::Result<()>) -> Result<bool, LinkError> {
+        let mut links = self.links.lock().unwrap();
+        if links.contains(&link) {
+            return Ok(false);
+        }
+        links.check(&link)?;
+        persist(&link)?;
+        Ok(links.insert(link))
+    }
+
+    fn delete_link(&self, link: Link, persist: impl FnOnce(&Link) -> io::Result<()>) -> io::Result<bool> {
+        let mut links = self.links.lock().unwrap();
+        if !links.contains(&link) {
+            return Ok(false);
+        }
+        persist(&link)?;
+        Ok(links.remove(&link))
+    }
+
+    /// Numbers a new comment, and keeps it only if `persist` succeeds.
+    fn insert_comment(
+        &self,
+        ticket_id: TicketId,
+        draft: CommentDraft,
+        created_at: SystemTime,
+        persist: impl FnOnce(&Comment) -> io::Result<()>,
+    ) -> io::Result<Comment> {
+        let mut comments = self.comments.lock().unwrap();
+        let ticket_comments = comments.entry(ticket_id).or_default();
+        let comment = Comment {
+            id: CommentId(ticket_comments.len() as u64),
+            ticket_id,
+            author: draft.author,
+            body: draft.body,
+            created_at,
+        };
+        persist(&comment)?;
+        ticket_comments.push(comment.clone());
+        Ok(comment)
+    }
+}
+
+impl TicketRepository for InMemoryRepository {
+    fn get(&self, id: TicketId) -> Option<Arc<RwLock<Ticket>>> {
+        self.tickets.get(&id).cloned()
+    }
+
+    fn next_id(&self) -> TicketId {
+        TicketId(self.counter)
+    }
+
+    fn insert(&mut self, ticket: Ticket, entry: HistoryEntry) -> io::Result<Arc<RwLock<Ticket>>> {
+        self.counter = self.counter.max(ticket.id.0 + 1);
+        let id = ticket.id;
+        let ticket = Arc::new(RwLock::new(ticket));
+        self.tickets.insert(id, ticket.clone());
+        self.record([entry]);
+        Ok(ticket)
+    }
+
+    fn insert_all(&mut self, tickets: Vec<Ticket>, entries: Vec<HistoryEntry>) -> io::Result<()> {
+        for ticket in tickets {
+            self.counter = self.counter.max(ticket.id.0 + 1);
+            self.tickets.insert(ticket.id, Arc::new(RwLock::new(ticket)));
+        }
+        self.record(entries);
+        Ok(())
+    }
+
+    fn update(&self, _ticket: &Ticket, entry: HistoryEntry) -> io::Result<()> {
+        self.record([entry]);
+        Ok(())
+    }
+
+    fn delete(&mut self, id: TicketId, entry: HistoryEntry) -> io::Result<Option<Arc<RwLock<Ticket>>>> {
+        let removed = self.tickets.remove(&id);
+        if removed.is_some() {
+            self.record([entry]);
+        }
+        self.comments.get_mut().unwrap().remove(&id);
+        self.links.get_mut().unwrap().remove_ticket(id);
+        Ok(removed)
+    }
+
+    fn scan(&self, after: Option<TicketId>) -> Box<dyn Iterator<Item = Arc<RwLock<Ticket>>> + Send + '_> {
+        let lower = after.map_or(Unbounded, Excluded);
+        Box::new(self.tickets.range((lower, Unbounded)).map(|(_, t)| t.clone()))
+    }
+
+    fn add_comment(&self, ticket_id: TicketId, draft: CommentDraft, created_at: SystemTime) -> io::Result<Comment> {
+        self.insert_comment(ticket_id, draft, created_at, |_| Ok(()))
+    }
+
+    fn comments(&self, ticket_id: TicketId, after: Option<CommentId>, limit: usize) -> Vec<Comment> {
+        let comments = self.comments.lock().unwrap();
+        let ticket_comments = comments.get(&ticket_id).map(Vec::as_slice).unwrap_or_default();
+        let skip = after.map_or(0, |after| after.0 as usize + 1);
+        ticket_comments.iter().skip(skip).take(limit).cloned().collect()
+    }
+
+    fn add_link(&self, link: Link) -> Result<bool, LinkError> {
+        self.insert_link(link, |_| Ok(()))
+    }
+
+    fn remove_link(&self, link: Link) -> io::Result<bool> {
+        self.delete_link(link, |_| Ok(()))
+    }
+
+    fn links_of(&self, id: TicketId) -> Vec<Link> {
+        self.links.lock().unwrap().links_of(id)
+    }
+
+    fn blockers(&self, id: TicketId) -> Vec<TicketId> {
+        self.links.lock().unwrap().blockers(id)
+    }
+
+    fn history(&self, id: TicketId) -> Vec<HistoryEntry> {
+        self.history.lock().unwrap().get(&id).cloned().unwrap_or_default()
+    }
+}
+
+/// Keeps tickets in memory, and writes every change ahead to a `FileStorage`
+/// so that they survive restarts.
+pub struct FileRepository {
+    memory: InMemoryRepository,
+    storage: FileStorage,
+}
+
+impl FileRepository {
+    /// Opens the storage in `dir` and replays it.
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let storage = FileStorage::open(dir)?;
+        let (snapshot, history) = storage.recover()?;
+        let memory = InMemoryRepository::from_snapshot(snapshot, history);
+        Ok(Self { memory, storage })
+    }
+}
+
+impl TicketRepository for FileRepository {
+    fn get(&self, id: TicketId) -> Option<Arc<RwLock<Ticket>>> {
+        self.memory.get(id)
+    }
+
+    fn next_id(&self) -> TicketId {
+        self.memory.next_id()
+    }
+
+    fn insert(&mut self, ticket: Ticket, entry: HistoryEntry) -> io::Result<Arc<RwLock<Ticket>>> {
+        self.storage.append(&Record::Created { ticket: ticket.clone(), entry: entry.clone() })?;
+        self.memory.insert(ticket, entry)
+    }
+
+    fn insert_all(&mut self, tickets: Vec<Ticket>, entries: Vec<HistoryEntry>) -> io::Result<()> {
+        self.storage.append(&Record::Imported {
+            tickets: tickets.iter().cloned().map(StoredTicket).collect(),
+            entries: entries.clone(),
+        })?;
+        self.memory.insert_all(tickets, entries)
+    }
+
+    fn update(&self, ticket: &Ticket, entry: HistoryEntry) -> io::Result<()> {
+        self.storage.append(&Record::Updated { ticket: ticket.clone(), entry: entry.clone() })?;
+        self.memory.update(ticket, entry)
+    }
+
+    fn delete(&mut self, id: TicketId, entry: HistoryEntry) -> io::Result<Option<Arc<RwLock<Ticket>>>> {
+        if self.memory.get(id).is_none() {
+            return Ok(None);
+        }
+        self.storage.append(&Record::Removed { id, entry: entry.clone() })?;
+        self.memory.delete(id, entry)
+    }
+
+    fn scan(&self, after: Option<TicketId>) -> Box<dyn Iterator<Item = Arc<RwLock<Ticket>>> + Send + '_> {
+        self.memory.scan(after)
+    }
+
+    fn add_comment(&self, ticket_id: TicketId, draft: CommentDraft, created_at: SystemTime) -> io::Result<Comment> {
+        self.memory.insert_comment(ticket_id, draft, created_at, |comment| {
+            self.storage.append(&Record::Commented(comment.clone()))
+        })
+    }
+
+    fn comments(&self, ticket_id: TicketId, after: Option<CommentId>, limit: usize) -> Vec<Comment> {
+        self.memory.comments(ticket_id, after, limit)
+    }
+
+    fn add_link(&self, link: Link) -> Result<bool, LinkError> {
+        self.memory.insert_link(link, |link| self.storage.append(&Record::Linked(*link)))
+    }
+
+    fn remove_link(&self, link: Link) -> io::Result<bool> {
+        self.memory.delete_link(link, |link| self.storage.append(&Record::Unlinked(*link)))
+    }
+
+    fn links_of(&self, id: TicketId) -> Vec<Link> {
+        self.memory.links_of(id)
+    }
+
+    fn blockers(&self, id: TicketId) -> Vec<TicketId> {
+        self.memory.blockers(id)
+    }
+
+    fn history(&self, id: TicketId) -> Vec<HistoryEntry> {
+        self.memory.history(id)
+    }
+
+    fn snapshot(&mut self, tickets: Vec<Ticket>) -> io::Result<()> {
+        let snapshot = Snapshot {
+            counter: self.memory.counter,
+            tickets: tickets.into_iter().map(StoredTicket).collect(),
+            comments: self.memory.comments.get_mut().unwrap().values().flatten().cloned().map(StoredComment).collect(),
+            links: self.memory.links.get_mut().unwrap().iter().collect(),
+        };
+        self.storage.snapshot(&snapshot)
+    }
+}
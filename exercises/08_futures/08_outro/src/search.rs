@@ -0,0 +1,182 @@
+use std::collections::{BTreeMap, HashMap};
+use crate::data::Ticket;
+use crate::store::TicketId;
+
+/// Matches in the title count this many times more than in the description.
+const TITLE_WEIGHT: u32 = 3;
+
+/// Splits `text` into lowercase words made of letters and digits.
+pub fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+/// Weighted occurrences of each term of a ticket.
+fn terms(ticket: &Ticket) -> HashMap<String, u32> {
+    let mut terms = HashMap::new();
+    for term in tokenize(&ticket.title.0) {
+        *terms.entry(term).or_default() += TITLE_WEIGHT;
+    }
+    for term in tokenize(&ticket.description.0) {
+        *terms.entry(term).or_default() += 1;
+    }
+    terms
+}
+
+/// Whether every word of `query` is a prefix of some word of `ticket`.
+pub fn matches(query: &str, ticket: &Ticket) -> bool {
+    let terms = terms(ticket);
+    tokenize(query).all(|word| terms.keys().any(|term| term.starts_with(&word)))
+}
+
+/// An inverted index of ticket titles and descriptions.
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    /// For each term, the weighted number of occurrences in each ticket.
+    postings: BTreeMap<String, BTreeMap<TicketId, u32>>,
+    /// The terms of each ticket, to unindex them when it changes.
+    documents: BTreeMap<TicketId, Vec<String>>,
+}
+
+impl SearchIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Indexes `ticket`, replacing what was indexed for it before.
+    pub fn insert(&mut self, ticket: &Ticket) {
+        self.remove(ticket.id);
+        let terms = terms(ticket);
+        self.documents.insert(ticket.id, terms.keys().cloned().collect());
+        for (term, count) in terms {
+            self.postings.entry(term).or_default().insert(ticket.id, count);
+        }
+    }
+
+    pub fn remove(&mut self, id: TicketId) {
+        for term in self.documents.remove(&id).unwrap_or_default() {
+            if let Some(postings) = self.postings.get_mut(&term) {
+                postings.remove(&id);
+                if postings.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+    }
+
+    /// Returns up to `limit` tickets in which every word of `query` is the prefix
+    /// of some term, best matches first.
+    ///
+    /// Each word scores the occurrences of the terms it matches, weighted by
+    /// how rare the term is and by how much of it the word covers.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<(TicketId, f64)> {
+        let documents = self.documents.len() as f64;
+        let mut scores: Option<BTreeMap<TicketId, f64>> = None;
+        for word in tokenize(query) {
+            let mut word_scores = BTreeMap::new();
+            let matching = self.postings.range(word.clone()..)
+                .take_while(|(term, _)| term.starts_with(&word));
+            for (term, postings) in matching {
+                let rarity = (1.0 + documents / postings.len() as f64).ln();
+                let coverage = word.len() as f64 / term.len() as f64;
+                for (&id, &count) in postings {
+                    *word_scores.entry(id).or_insert(0.0) += count as f64 * rarity * coverage;
+                }
+            }
+            // Every word has to match.
+            scores = Some(match scores {
+                None => word_scores,
+                Some(scores) => scores.into_iter()
+                    .filter_map(|(id, score)| word_scores.get(&id).map(|s| (id, score + s)))
+                    .collect(),
+            });
+        }
+
+        let mut ranked: Vec<_> = scores.unwrap_or_default().into_iter().collect();
+        ranked.sort_by(|(a_id, a), (b_id, b)| b.total_cmp(a).then(a_id.cmp(b_id)));
+        ranked.truncate(limit);
+        ranked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use super::*;
+    use crate::data::{Priority, Status};
+
+    fn ticket(id: u64, title: &str, description: &str) -> Ticket {
+        Ticket {
+            id: TicketId(id),
+            title: title.try_into().unwrap(),
+            description: description.try_into().unwrap(),
+            status: Status::ToDo,
+            priority: Priority::default(),
+            assignee: None,
+            labels: BTreeSet::new(),
+            version: 1,
+        }
+    }
+
+    fn index(tickets: &[Ticket]) -> SearchIndex {
+        let mut index = SearchIndex::new();
+        for ticket in tickets {
+            index.insert(ticket);
+        }
+        index
+    }
+
+    #[test]
+    fn test_tokenize() {
+        let words: Vec<_> = tokenize("Sign-in page: HTTP 500, again!").collect();
+        assert_eq!(words, vec!["sign", "in", "page", "http", "500", "again"]);
+    }
+
+    #[test]
+    fn test_title_matches_are_weighted() {
+        let index = index(&[
+            ticket(0, "Login fails", "Nothing else"),
+            ticket(1, "Crash", "On login"),
+        ]);
+        let scores: BTreeMap<_, _> = index.search("login", 10).into_iter().collect();
+        let ratio = scores[&TicketId(0)] / scores[&TicketId(1)];
+        assert!((ratio - TITLE_WEIGHT as f64).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_prefix_matches_keep_the_title_weight() {
+        let index = index(&[
+            ticket(0, "Login fails", "Nothing else"),
+            ticket(1, "Crash", "On login"),
+        ]);
+        let hits = index.search("log", 10);
+        assert_eq!(hits.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![TicketId(0), TicketId(1)]);
+        let ratio = hits[0].1 / hits[1].1;
+        assert!((ratio - TITLE_WEIGHT as f64).abs() < 1e-9);
+        // Covering less of the term scores less.
+        assert!(hits[0].1 < index.search("login", 10)[0].1);
+    }
+
+    #[test]
+    fn test_every_word_has_to_match() {
+        let index = index(&[ticket(0, "Login fails", "On Safari"), ticket(1, "Login works", "On Firefox")]);
+        let hits: Vec<_> = index.search("login safari", 10).into_iter().map(|(id, _)| id).collect();
+        assert_eq!(hits, vec![TicketId(0)]);
+        assert!(index.search("login chrome", 10).is_empty());
+        assert!(index.search("", 10).is_empty());
+        assert!(matches("log saf", &ticket(0, "Login fails", "On Safari")));
+        assert!(!matches("gin", &ticket(0, "Login fails", "On Safari")));
+    }
+
+    #[test]
+    fn test_reindexing_replaces_the_terms() {
+        let mut index = index(&[ticket(0, "Login fails", "On Safari")]);
+        index.insert(&ticket(0, "Logout fails", "On Safari"));
+        assert!(index.search("login", 10).is_empty());
+        assert_eq!(index.search("logout", 10).len(), 1);
+        index.remove(TicketId(0));
+        assert!(index.search("safari", 10).is_empty());
+        assert!(index.postings.is_empty());
+    }
+}
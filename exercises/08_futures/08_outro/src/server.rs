@@ -1,13 +1,36 @@
+use std::collections::BTreeSet;
 use std::convert::TryInto;
+use futures::channel::mpsc;
+use futures::io::BufReader;
+use futures::{SinkExt, TryStreamExt};
 use thiserror::Error;
-use ticket_fields::{TicketDescription, TicketTitle};
+use ticket_fields::{
+    TicketAssignee, TicketAssigneeError, TicketComment, TicketCommentError, TicketDescription,
+    TicketDescriptionError, TicketLabel, TicketLabelError, TicketTitle, TicketTitleError,
+};
 use tide::prelude::*;
 use tide::{Body, Request, Response, StatusCode};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 use tokio::net::TcpListener;
-use MyError::NotFound;
-use crate::data::{Status, Ticket, TicketDraft};
-use crate::server::MyError::BadRequest;
-use crate::store::{TicketId, TicketStore};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use MyError::{AuthorMismatch, BadRequest, Internal, InvalidField, InvalidJson, NotFound, PayloadTooLarge, PreconditionFailed, Unavailable};
+use crate::auth::{Authenticate, Tokens, User};
+use crate::data::{Comment, CommentDraft, Priority, Status, Ticket, TicketDraft, TicketFilter, TicketPatch};
+use crate::events::{Subscription, TicketChange, TicketEvent};
+use crate::history::HistoryEntry;
+use crate::links::{Link, LinkError, Relation};
+use crate::metrics::RecordMetrics;
+use crate::policy::{Action, Forbidden, Policy};
+use crate::middleware::{BodyLimit, RateLimit, RateLimiter};
+use crate::openapi::{ApiDoc, TicketAssigneeSchema, TicketLabelSchema};
+use crate::repository::TicketRepository;
+use crate::search;
+use crate::shutdown::{self, Shutdown};
+use crate::store::{CommentId, StoreError, TicketId, TicketStore};
+use crate::{bulk, websocket};
+use crate::workflow::IllegalTransition;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(remote = "TicketTitle")]
@@ -17,87 +40,751 @@ pub struct TicketTitleSerializer(String);
 #[serde(remote = "TicketDescription")]
 pub struct TicketDescriptionSerializer(String);
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(remote = "TicketComment")]
+pub struct TicketCommentSerializer(String);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(remote = "TicketAssignee")]
+pub struct TicketAssigneeSerializer(String);
+
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema)]
 #[serde(remote = "Ticket")]
+#[schema(as = Ticket)]
 pub struct TicketSerializer {
     pub id: TicketId,
-    #[serde(with="TicketTitleSerializer")] pub title: TicketTitle,
-    #[serde(with="TicketDescriptionSerializer")] pub description: TicketDescription,
+    #[serde(with="TicketTitleSerializer")]
+    #[schema(value_type = TicketTitleSerializer)] pub title: TicketTitle,
+    #[serde(with="TicketDescriptionSerializer")]
+    #[schema(value_type = TicketDescriptionSerializer)] pub description: TicketDescription,
     pub status: Status,
+    // Defaults for tickets stored before these fields existed.
+    #[serde(default)]
+    pub priority: Priority,
+    #[serde(default, with = "optional_assignee")]
+    #[schema(value_type = Option<TicketAssigneeSchema>)] pub assignee: Option<TicketAssignee>,
+    #[serde(default, with = "label_set")]
+    #[schema(value_type = Vec<TicketLabelSchema>)] pub labels: BTreeSet<TicketLabel>,
+    pub version: u64,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema)]
+#[serde(remote = "Comment")]
+#[schema(as = Comment)]
+pub struct CommentSerializer {
+    pub id: CommentId,
+    pub ticket_id: TicketId,
+    #[serde(with="TicketAssigneeSerializer")]
+    #[schema(value_type = TicketAssigneeSchema)] pub author: TicketAssignee,
+    #[serde(with="TicketCommentSerializer")]
+    #[schema(value_type = TicketCommentSerializer)] pub body: TicketComment,
+    #[serde(with = "rfc3339")]
+    #[schema(value_type = String, format = DateTime)] pub created_at: SystemTime,
+}
+
+/// `serde(with)` for `Option<TicketAssignee>`, as an optional string.
+mod optional_assignee {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use ticket_fields::TicketAssignee;
+
+    pub fn serialize<S: Serializer>(value: &Option<TicketAssignee>, serializer: S) -> Result<S::Ok, S::Error> {
+        value.as_ref().map(|assignee| &assignee.0).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<TicketAssignee>, D::Error> {
+        Ok(Option::<String>::deserialize(deserializer)?.map(TicketAssignee))
+    }
+}
+
+/// `serde(with)` for a set of `TicketLabel`, as an array of strings.
+mod label_set {
+    use std::collections::BTreeSet;
+    use serde::{Deserialize, Deserializer, Serializer};
+    use ticket_fields::TicketLabel;
+
+    pub fn serialize<S: Serializer>(value: &BTreeSet<TicketLabel>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(value.iter().map(|label| &label.0))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BTreeSet<TicketLabel>, D::Error> {
+        Ok(Vec::<String>::deserialize(deserializer)?.into_iter().map(TicketLabel).collect())
+    }
+}
+
+/// `serde(with)` for a `SystemTime`, as an RFC 3339 string in UTC.
+pub mod rfc3339 {
+    use std::time::SystemTime;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&humantime::format_rfc3339_millis(*value))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SystemTime, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        humantime::parse_rfc3339(&value).map_err(de::Error::custom)
+    }
+}
+
+/// How many labels a ticket can have.
+pub const MAX_LABELS: usize = 20;
+
+fn parse_labels(labels: Vec<String>) -> Result<BTreeSet<TicketLabel>, FieldError> {
+    let labels = labels.into_iter().map(TicketLabel::try_from).collect::<Result<BTreeSet<_>, _>>()?;
+    if labels.len() > MAX_LABELS {
+        let message = format!("A ticket cannot have more than {} labels", MAX_LABELS);
+        return Err(FieldError { field: "labels", code: "labels_too_many", message });
+    }
+    Ok(labels)
+}
+
+/// Deserializes a field that is present, so that `null` gives `Some(None)`
+/// while a missing field is left to `serde(default)`, i.e. `None`.
+fn present<'de, D: serde::Deserializer<'de>, T: Deserialize<'de>>(deserializer: D) -> Result<Option<T>, D::Error> {
+    T::deserialize(deserializer).map(Some)
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+#[schema(value_type = TicketSerializer)]
 pub struct GetTicketResponse(#[serde(with="TicketSerializer")] pub Ticket);
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema)]
 pub struct CreateTicketRequest {
+    #[schema(value_type = TicketTitleSerializer)]
     pub title: String,
+    #[schema(value_type = TicketDescriptionSerializer)]
     pub description: String,
+    #[serde(default)]
+    pub priority: Priority,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<TicketAssigneeSchema>)]
+    pub assignee: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[schema(value_type = Vec<TicketLabelSchema>)]
+    pub labels: Vec<String>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct CreateTicketResponse {
     pub ticket_id: TicketId,
 }
 
+impl From<TicketDraft> for CreateTicketRequest {
+    fn from(draft: TicketDraft) -> Self {
+        CreateTicketRequest {
+            title: draft.title.0,
+            description: draft.description.0,
+            priority: draft.priority,
+            assignee: draft.assignee.map(|a| a.0),
+            labels: draft.labels.into_iter().map(|l| l.0).collect(),
+        }
+    }
+}
+
 impl TryInto<TicketDraft> for CreateTicketRequest {
-    type Error = anyhow::Error;
+    type Error = FieldError;
 
     fn try_into(self) -> Result<TicketDraft, Self::Error> {
         let title = self.title.try_into()?;
         let description = self.description.try_into()?;
-        let result = TicketDraft { title, description };
+        let assignee = self.assignee.map(TicketAssignee::try_from).transpose()?;
+        let labels = parse_labels(self.labels)?;
+        let result = TicketDraft { title, description, priority: self.priority, assignee, labels };
+        Ok(result)
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListTicketsQuery {
+    pub status: Option<Status>,
+    pub priority: Option<Priority>,
+    pub assignee: Option<String>,
+    /// Only return tickets with this label.
+    pub label: Option<String>,
+    /// Only return tickets with a greater id.
+    pub after: Option<u64>,
+    #[param(minimum = 1, maximum = 500)]
+    pub limit: Option<usize>,
+}
+
+impl ListTicketsQuery {
+    pub fn new(filter: &TicketFilter, after: Option<TicketId>, limit: Option<usize>) -> Self {
+        ListTicketsQuery {
+            status: filter.status,
+            priority: filter.priority,
+            assignee: filter.assignee.as_ref().map(|a| a.0.clone()),
+            label: filter.label.as_ref().map(|l| l.0.clone()),
+            after: after.map(|id| id.0),
+            limit,
+        }
+    }
+
+    pub fn filter(&self) -> Result<TicketFilter, FieldError> {
+        Ok(TicketFilter {
+            status: self.status,
+            priority: self.priority,
+            assignee: self.assignee.as_deref().map(TicketAssignee::try_from).transpose()?,
+            label: self.label.as_deref().map(TicketLabel::try_from).transpose()?,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct ListTicketsResponse {
+    pub tickets: Vec<GetTicketResponse>,
+    /// Pass this as `after` to fetch the next page, absent on the last page.
+    pub next_after: Option<TicketId>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchTicketsQuery {
+    /// Words to look for in titles and descriptions. Each one can be the start of a word.
+    pub q: String,
+    #[param(minimum = 1, maximum = 500)]
+    pub limit: Option<usize>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct SearchTicketsResponse {
+    /// Best matches first.
+    pub hits: Vec<SearchHit>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct SearchHit {
+    pub score: f64,
+    pub ticket: GetTicketResponse,
+}
+
+pub const DEFAULT_PAGE_SIZE: usize = 50;
+pub const MAX_PAGE_SIZE: usize = 500;
+
+/// A link, as seen from the ticket in the path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TicketLink {
+    pub relation: Relation,
+    /// The ticket at the other end of the link.
+    #[param(value_type = u64)]
+    pub ticket_id: TicketId,
+}
+
+impl TicketLink {
+    pub fn new(ticket_id: TicketId, link: &Link) -> Self {
+        let (relation, other) = Relation::of(ticket_id, link);
+        TicketLink { relation, ticket_id: other }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct ListLinksResponse {
+    pub links: Vec<TicketLink>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct TicketBlockersResponse {
+    /// Tickets blocking this one, directly or through other tickets, closest first.
+    pub blockers: Vec<TicketId>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct TicketHistoryResponse {
+    /// Oldest first.
+    pub entries: Vec<HistoryEntry>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+#[schema(value_type = CommentSerializer)]
+pub struct GetCommentResponse(#[serde(with="CommentSerializer")] pub Comment);
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct CreateCommentRequest {
+    /// Defaults to the authenticated user, who can't post as anyone else.
+    /// Required when requests are not authenticated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<TicketAssigneeSchema>)]
+    pub author: Option<String>,
+    #[schema(value_type = TicketCommentSerializer)]
+    pub body: String,
+}
+
+impl CreateCommentRequest {
+    /// The comment to post, `user` being the authenticated one if any.
+    fn into_draft(self, user: Option<&User>) -> Result<CommentDraft, MyError> {
+        let author = match (self.author, user) {
+            (Some(author), Some(user)) if author != user.name.0 => return Err(AuthorMismatch(user.name.0.clone())),
+            (_, Some(user)) => user.name.clone(),
+            (Some(author), None) => TicketAssignee::try_from(author)
+                .map_err(|e| FieldError { field: "author", ..FieldError::from(e) })?,
+            (None, None) => {
+                let message = "An author is required when requests are not authenticated".to_string();
+                return Err(BadRequest { field: "author", message });
+            }
+        };
+        let body = self.body.try_into().map_err(FieldError::from)?;
+        Ok(CommentDraft { author, body })
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListCommentsQuery {
+    /// Only return comments with a greater id.
+    pub after: Option<u64>,
+    #[param(minimum = 1, maximum = 500)]
+    pub limit: Option<usize>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct ListCommentsResponse {
+    /// Oldest first.
+    pub comments: Vec<GetCommentResponse>,
+    /// Pass this as `after` to fetch the next page, absent on the last page.
+    pub next_after: Option<CommentId>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct PatchTicketRequest {
+    #[schema(value_type = Option<TicketTitleSerializer>)]
+    pub title: Option<String>,
+    #[schema(value_type = Option<TicketDescriptionSerializer>)]
+    pub description: Option<String>,
+    pub status: Option<Status>,
+    pub priority: Option<Priority>,
+    /// `null` unassigns the ticket.
+    #[serde(default, skip_serializing_if = "Option::is_none", deserialize_with = "present")]
+    #[schema(value_type = Option<TicketAssigneeSchema>, nullable)]
+    pub assignee: Option<Option<String>>,
+    /// Replaces all the labels of the ticket.
+    #[schema(value_type = Option<Vec<TicketLabelSchema>>)]
+    pub labels: Option<Vec<String>>,
+}
+
+impl From<TicketPatch> for PatchTicketRequest {
+    fn from(patch: TicketPatch) -> Self {
+        PatchTicketRequest {
+            title: patch.title.map(|t| t.0),
+            description: patch.description.map(|d| d.0),
+            status: patch.status,
+            priority: patch.priority,
+            assignee: patch.assignee.map(|assignee| assignee.map(|a| a.0)),
+            labels: patch.labels.map(|labels| labels.into_iter().map(|l| l.0).collect()),
+        }
+    }
+}
+
+impl TryInto<TicketPatch> for PatchTicketRequest {
+    type Error = FieldError;
+
+    fn try_into(self) -> Result<TicketPatch, Self::Error> {
+        let title = self.title.map(TicketTitle::try_from).transpose()?;
+        let description = self.description.map(TicketDescription::try_from).transpose()?;
+        let assignee = self.assignee
+            .map(|assignee| assignee.map(TicketAssignee::try_from).transpose())
+            .transpose()?;
+        let labels = self.labels.map(parse_labels).transpose()?;
+        let result = TicketPatch {
+            title,
+            description,
+            status: self.status,
+            priority: self.priority,
+            assignee,
+            labels,
+        };
         Ok(result)
     }
 }
 
+/// Listens on localhost, on the given port or on a random free one.
 pub async fn listen(port: Option<u16>) -> std::io::Result<TcpListener> {
-    let bind_addr = format!("127.0.0.1:{}", port.unwrap_or(0));
-    let listener = TcpListener::bind(bind_addr).await?;
+    listen_on(SocketAddr::from((Ipv4Addr::LOCALHOST, port.unwrap_or(0)))).await
+}
+
+pub async fn listen_on(address: SocketAddr) -> std::io::Result<TcpListener> {
+    let listener = TcpListener::bind(address).await?;
     let local_addr = listener.local_addr()?;
     println!("Server listening on {}", local_addr);
     Ok(listener)
 }
 
+/// A request field that failed validation.
+#[derive(Error, Debug)]
+#[error("{message}")]
+pub struct FieldError {
+    pub field: &'static str,
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl From<TicketTitleError> for FieldError {
+    fn from(error: TicketTitleError) -> Self {
+        let code = match error {
+            TicketTitleError::Empty => "title_empty",
+            TicketTitleError::TooLong => "title_too_long",
+        };
+        FieldError { field: "title", code, message: error.to_string() }
+    }
+}
+
+impl From<TicketDescriptionError> for FieldError {
+    fn from(error: TicketDescriptionError) -> Self {
+        let code = match error {
+            TicketDescriptionError::Empty => "description_empty",
+            TicketDescriptionError::TooLong => "description_too_long",
+        };
+        FieldError { field: "description", code, message: error.to_string() }
+    }
+}
+
+impl From<TicketCommentError> for FieldError {
+    fn from(error: TicketCommentError) -> Self {
+        let code = match error {
+            TicketCommentError::Empty => "comment_empty",
+            TicketCommentError::TooLong => "comment_too_long",
+        };
+        FieldError { field: "body", code, message: error.to_string() }
+    }
+}
+
+impl From<TicketAssigneeError> for FieldError {
+    fn from(error: TicketAssigneeError) -> Self {
+        let code = match error {
+            TicketAssigneeError::Empty => "assignee_empty",
+            TicketAssigneeError::TooLong => "assignee_too_long",
+            TicketAssigneeError::InvalidCharacter(_) => "assignee_invalid",
+        };
+        FieldError { field: "assignee", code, message: error.to_string() }
+    }
+}
+
+impl From<TicketLabelError> for FieldError {
+    fn from(error: TicketLabelError) -> Self {
+        let code = match error {
+            TicketLabelError::Empty => "label_empty",
+            TicketLabelError::TooLong => "label_too_long",
+            TicketLabelError::InvalidCharacter(_) => "label_invalid",
+        };
+        FieldError { field: "labels", code, message: error.to_string() }
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum MyError {
-    #[error("Bad request: {0}")]
-    BadRequest(String),
+    #[error("Bad request: {message}")]
+    BadRequest { field: &'static str, message: String },
+
+    #[error("Invalid JSON: {0}")]
+    InvalidJson(String),
+
+    #[error("Malformed ticket: {0}")]
+    InvalidField(#[from] FieldError),
+
+    #[error("Ticket {} not found", .0.0)]
+    NotFound(TicketId),
+
+    #[error("Precondition failed: {0}")]
+    PreconditionFailed(String),
+
+    #[error(transparent)]
+    IllegalTransition(IllegalTransition),
+
+    #[error(transparent)]
+    InvalidLink(LinkError),
+
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+
+    #[error("Forbidden: {0}")]
+    Forbidden(Forbidden),
+
+    #[error("Comments can only be posted as {0}")]
+    AuthorMismatch(String),
+
+    #[error("Too many requests, retry in {}s", retry_after_secs(.0))]
+    TooManyRequests(Duration),
+
+    #[error("Request body is larger than {0} bytes")]
+    PayloadTooLarge(usize),
+
+    #[error("Service unavailable: {0}")]
+    Unavailable(String),
+
+    #[error("Internal error: {0}")]
+    Internal(String),
+}
+
+/// Whole seconds, as sent in `Retry-After`: never 0, which would invite an immediate retry.
+fn retry_after_secs(retry_after: &Duration) -> u64 {
+    retry_after.as_secs_f64().ceil().max(1.0) as u64
+}
+
+impl MyError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            BadRequest { .. } | InvalidJson(_) | InvalidField(_) => StatusCode::BadRequest,
+            NotFound(_) => StatusCode::NotFound,
+            PreconditionFailed(_) => StatusCode::PreconditionFailed,
+            MyError::IllegalTransition(_) => StatusCode::Conflict,
+            MyError::InvalidLink(LinkError::SelfLink) => StatusCode::BadRequest,
+            MyError::InvalidLink(LinkError::Io(_)) => StatusCode::InternalServerError,
+            MyError::InvalidLink(_) => StatusCode::Conflict,
+            MyError::Unauthorized(_) => StatusCode::Unauthorized,
+            MyError::Forbidden(_) | AuthorMismatch(_) => StatusCode::Forbidden,
+            PayloadTooLarge(_) => StatusCode::PayloadTooLarge,
+            MyError::TooManyRequests(_) => StatusCode::TooManyRequests,
+            Unavailable(_) => StatusCode::ServiceUnavailable,
+            Internal(_) => StatusCode::InternalServerError,
+        }
+    }
+
+    /// A stable identifier of the error, meant for clients to match on.
+    pub fn code(&self) -> &'static str {
+        match self {
+            BadRequest { .. } => "invalid_parameter",
+            InvalidJson(_) => "invalid_json",
+            InvalidField(e) => e.code,
+            NotFound(_) => "ticket_not_found",
+            PreconditionFailed(_) => "precondition_failed",
+            MyError::IllegalTransition(_) => "illegal_transition",
+            MyError::InvalidLink(LinkError::SelfLink) => "link_to_self",
+            MyError::InvalidLink(LinkError::Cycle(_)) => "link_cycle",
+            MyError::InvalidLink(LinkError::ParentAlreadySet { .. }) => "parent_already_set",
+            MyError::InvalidLink(LinkError::Io(_)) => "internal_error",
+            MyError::Unauthorized(_) => "unauthorized",
+            MyError::Forbidden(_) => "forbidden",
+            AuthorMismatch(_) => "author_mismatch",
+            PayloadTooLarge(_) => "payload_too_large",
+            MyError::TooManyRequests(_) => "rate_limited",
+            Unavailable(_) => "service_unavailable",
+            Internal(_) => "internal_error",
+        }
+    }
+
+    /// The request field or parameter at fault, if any.
+    pub fn field(&self) -> Option<&'static str> {
+        match self {
+            BadRequest { field, .. } => Some(field),
+            InvalidField(e) => Some(e.field),
+            MyError::IllegalTransition(_) => Some("status"),
+            MyError::InvalidLink(_) => Some("ticket_id"),
+            AuthorMismatch(_) => Some("author"),
+            _ => None,
+        }
+    }
+}
+
+impl From<StoreError> for MyError {
+    fn from(error: StoreError) -> Self {
+        match error {
+            StoreError::NotFound(id) => NotFound(id),
+            e @ StoreError::VersionMismatch { .. } => PreconditionFailed(e.to_string()),
+            StoreError::IllegalTransition(e) => MyError::IllegalTransition(e),
+            StoreError::InvalidLink(LinkError::Io(e)) | StoreError::Io(e) => Internal(e.to_string()),
+            StoreError::InvalidLink(e) => MyError::InvalidLink(e),
+        }
+    }
+}
+
+/// The `data` of the events streamed by `GET /tickets/events`.
+/// `ticket` is missing from `deleted` events.
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct TicketEventData {
+    pub ticket_id: TicketId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ticket: Option<GetTicketResponse>,
+}
+
+impl From<TicketChange> for TicketEventData {
+    fn from(change: TicketChange) -> Self {
+        let ticket_id = change.ticket_id();
+        let ticket = match change {
+            TicketChange::Created(ticket) | TicketChange::Updated(ticket) => Some(GetTicketResponse(ticket)),
+            TicketChange::Deleted(_) => None,
+        };
+        TicketEventData { ticket_id, ticket }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ErrorResponse {
+    pub error: ErrorBody,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ErrorBody {
+    pub code: String,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    /// Only set for `forbidden` errors.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<Forbidden>,
+}
 
-    #[error("Not found: {0}")]
-    NotFound(String),
+impl From<&MyError> for ErrorResponse {
+    fn from(error: &MyError) -> Self {
+        let error = ErrorBody {
+            code: error.code().to_string(),
+            message: error.to_string(),
+            field: error.field().map(str::to_string),
+            reason: match error {
+                MyError::Forbidden(reason) => Some(reason.clone()),
+                _ => None,
+            },
+        };
+        ErrorResponse { error }
+    }
 }
 
 async fn error_handler(mut res: Response) -> tide::Result {
     if let Some(error) = res.downcast_error::<MyError>() {
-        let status_code = match error {
-            BadRequest(_) => { StatusCode::BadRequest }
-            NotFound(_) => { StatusCode::NotFound }
+        let status_code = error.status();
+        let body = Body::from_json(&ErrorResponse::from(error))?;
+        let header = match error {
+            MyError::Unauthorized(_) => Some(("WWW-Authenticate", "Bearer".to_string())),
+            MyError::TooManyRequests(retry_after) => Some(("Retry-After", retry_after_secs(retry_after).to_string())),
+            _ => None,
         };
-        let error_msg = error.to_string();
         res.set_status(status_code);
-        res.set_body(error_msg);
+        res.set_body(body);
+        if let Some((name, value)) = header {
+            res.insert_header(name, value);
+        }
     }
     Ok(res)
 }
 
+/// Knobs of the REST API, independent of how the server binary is configured.
+#[derive(Clone, Debug)]
+pub struct ServerSettings {
+    /// Requests with a larger body are rejected before it is read.
+    pub max_body_size: usize,
+    /// Same as `max_body_size`, for `POST /tickets/import`.
+    pub max_import_size: usize,
+    /// How long in-flight requests may take to complete once shutting down.
+    pub shutdown_timeout: Duration,
+    /// If set, every request must carry one of these tokens.
+    pub tokens: Option<Arc<Tokens>>,
+    /// What authenticated users may do. Ignored without `tokens`.
+    pub policy: Policy,
+    /// Applies to each token, and to the requests failing authentication from each IP address.
+    /// Without authentication, to every request from each IP address.
+    pub rate_limit: Option<RateLimit>,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        ServerSettings {
+            max_body_size: 64 * 1024,
+            max_import_size: 64 * 1024 * 1024,
+            shutdown_timeout: Duration::from_secs(30),
+            tokens: None,
+            policy: Policy::default(),
+            rate_limit: Some(RateLimit::default()),
+        }
+    }
+}
+
 pub async fn run_server(listener: TcpListener) -> std::io::Result<()> {
-    let store = TicketStore::new();
-    let mut app = tide::with_state(store);
+    run_server_with_store(listener, TicketStore::new()).await
+}
+
+pub async fn run_server_with_store<R: TicketRepository>(listener: TcpListener, store: TicketStore<R>) -> std::io::Result<()> {
+    run_server_with(listener, store, ServerSettings::default()).await
+}
+
+/// Serves until the process receives SIGTERM or SIGINT.
+pub async fn run_server_with<R: TicketRepository>(listener: TcpListener, store: TicketStore<R>, settings: ServerSettings) -> std::io::Result<()> {
+    let shutdown = Shutdown::new();
+    let signals = shutdown.trigger_on_signals();
+    let result = run_server_until(listener, store, settings, shutdown).await;
+    signals.abort();
+    result
+}
+
+/// A server being set up, keeping track of its routes to label metrics with them.
+struct Routes<R> {
+    app: tide::Server<TicketStore<R>>,
+    paths: Vec<&'static str>,
+}
+
+impl<R: TicketRepository> Routes<R> {
+    fn at(&mut self, path: &'static str) -> tide::Route<'_, TicketStore<R>> {
+        self.paths.push(path);
+        self.app.at(path)
+    }
+}
+
+/// Serves until `shutdown` is triggered, then lets in-flight requests complete
+/// within `settings.shutdown_timeout` and compacts the storage before returning.
+pub async fn run_server_until<R: TicketRepository>(listener: TcpListener, store: TicketStore<R>, settings: ServerSettings, shutdown: Shutdown) -> std::io::Result<()> {
+    let events_store = store.clone();
+    let events_shutdown = shutdown.clone();
+    // Event streams never end on their own: close them so they don't hold up the shutdown.
+    tokio::spawn(async move {
+        events_shutdown.triggered().await;
+        events_store.events().close();
+    });
+
+    // Set on each route, since imports get a larger limit.
+    let limit = BodyLimit(settings.max_body_size);
+    let mut routes = Routes { app: tide::with_state(store.clone()), paths: Vec::new() };
+    routes.at("/metrics").with(limit).get(metrics::<R>);
+    routes.at("/openapi.json").with(limit).get(openapi);
+    routes.at("/tickets").with(limit).post(new_ticket::<R>).get(list_tickets::<R>);
+    routes.at("/tickets/events").with(limit).get(ticket_events::<R>);
+    routes.at("/tickets/search").with(limit).get(search_tickets::<R>);
+    routes.at("/tickets/ws").with(limit).get(websocket::ticket_subscriptions::<R>);
+    routes.at("/tickets/import").with(BodyLimit(settings.max_import_size)).post(bulk::import_tickets::<R>);
+    routes.at("/tickets/export").with(limit).get(bulk::export_tickets::<R>);
+    routes.at("/tickets/:id").with(limit).get(get_ticket::<R>).patch(patch_ticket::<R>).delete(delete_ticket::<R>);
+    routes.at("/tickets/:id/history").with(limit).get(ticket_history::<R>);
+    routes.at("/tickets/:id/links").with(limit).post(link_ticket::<R>).get(list_links::<R>).delete(unlink_ticket::<R>);
+    routes.at("/tickets/:id/blockers").with(limit).get(ticket_blockers::<R>);
+    routes.at("/tickets/:id/comments").with(limit).post(new_comment::<R>).get(list_comments::<R>);
+
+    // Middleware of the server runs before the one of routes, even when registered after them.
+    let Routes { mut app, paths } = routes;
+    // Before the error handler, to record the status codes errors are turned into.
+    app.with(RecordMetrics { metrics: store.metrics().clone(), routes: paths.into() });
     app.with(tide::utils::After(error_handler));
-    app.at("/tickets").post(new_ticket);
-    app.at("/tickets/:id").get(get_ticket);
-    // Use the listener for the Tide app
-    app.listen(listener.into_std()?).await
+    let rate_limit = settings.rate_limit;
+    if let Some(tokens) = settings.tokens {
+        if let Some(rate_limit) = rate_limit {
+            app.with(RateLimiter::by_failed_authentication(rate_limit));
+        }
+        app.with(Authenticate { tokens, policy: Arc::new(settings.policy) });
+        if let Some(rate_limit) = rate_limit {
+            app.with(RateLimiter::by_token(rate_limit));
+        }
+    } else if let Some(rate_limit) = rate_limit {
+        app.with(RateLimiter::by_address(rate_limit));
+    }
+    if !shutdown::serve(listener, app, shutdown, settings.shutdown_timeout).await {
+        tide::log::warn!("Some requests were cut off by the shutdown timeout");
+    }
+    // Requests cut off by the timeout may still hold a ticket lock:
+    // waiting for the store write lock lets them complete their update.
+    store.write().await.snapshot().await
 }
 
-pub async fn new_ticket(mut req: Request<TicketStore>) -> tide::Result {
+#[utoipa::path(
+    post, path = "/tickets", request_body = CreateTicketRequest,
+    responses(
+        (status = 200, body = CreateTicketResponse),
+        (status = 400, body = ErrorResponse),
+    ),
+)]
+pub async fn new_ticket<R: TicketRepository>(mut req: Request<TicketStore<R>>) -> tide::Result {
+    authorize(&req, Action::Create)?;
     let ticket_request: CreateTicketRequest = req.body_json()
-        .await.map_err(|_| BadRequest("Failed to parse.".to_string()))?;
+        .await.map_err(|e| InvalidJson(e.to_string()))?;
 
-    let store = req.state();
-    let ticket_draft = ticket_request.try_into().map_err(|e| BadRequest(format!("Malformed ticket: {}", e)))?;
-    let id: TicketId = store.write().await.add_ticket(ticket_draft);
+    let store = store_for(&req);
+    let ticket_draft = ticket_request.try_into().map_err(MyError::from)?;
+    let id: TicketId = store.write().await.add_ticket(ticket_draft)?;
 
     let response_body = CreateTicketResponse { ticket_id: id };
 
@@ -106,20 +793,461 @@ pub async fn new_ticket(mut req: Request<TicketStore>) -> tide::Result {
     Ok(response)
 }
 
-pub async fn get_ticket(req: Request<TicketStore>) -> tide::Result {
+#[utoipa::path(
+    get, path = "/tickets", params(ListTicketsQuery),
+    responses(
+        (status = 200, body = ListTicketsResponse),
+        (status = 400, body = ErrorResponse),
+    ),
+)]
+pub async fn list_tickets<R: TicketRepository>(req: Request<TicketStore<R>>) -> tide::Result {
+    authorize(&req, Action::Read)?;
+    let query: ListTicketsQuery = req.query()
+        .map_err(|e| BadRequest { field: "query", message: e.to_string() })?;
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if limit == 0 || limit > MAX_PAGE_SIZE {
+        let message = format!("Limit must be between 1 and {}", MAX_PAGE_SIZE);
+        return Err(BadRequest { field: "limit", message }.into());
+    }
+
+    let filter = query.filter().map_err(MyError::from)?;
+    let store = req.state();
+    let tickets = store.read().await.list(query.after.map(TicketId), &filter, limit).await;
+    let next_after = if tickets.len() == limit { tickets.last().map(|t| t.id) } else { None };
+
+    let response_body = ListTicketsResponse {
+        tickets: tickets.into_iter().map(GetTicketResponse).collect(),
+        next_after,
+    };
+
+    let mut response = Response::new(StatusCode::Ok);
+    response.set_body(Body::from_json(&response_body)?);
+    Ok(response)
+}
+
+#[utoipa::path(
+    get, path = "/tickets/search", params(SearchTicketsQuery),
+    responses(
+        (status = 200, body = SearchTicketsResponse),
+        (status = 400, body = ErrorResponse),
+    ),
+)]
+pub async fn search_tickets<R: TicketRepository>(req: Request<TicketStore<R>>) -> tide::Result {
+    authorize(&req, Action::Read)?;
+    let query: SearchTicketsQuery = req.query()
+        .map_err(|e| BadRequest { field: "query", message: e.to_string() })?;
+    if search::tokenize(&query.q).next().is_none() {
+        let message = "Query must contain at least one word".to_string();
+        return Err(BadRequest { field: "q", message }.into());
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if limit == 0 || limit > MAX_PAGE_SIZE {
+        let message = format!("Limit must be between 1 and {}", MAX_PAGE_SIZE);
+        return Err(BadRequest { field: "limit", message }.into());
+    }
+
+    let store = req.state();
+    let hits = store.read().await.search(&query.q, limit).await;
+    let response_body = SearchTicketsResponse {
+        hits: hits.into_iter()
+            .map(|(ticket, score)| SearchHit { score, ticket: GetTicketResponse(ticket) })
+            .collect(),
+    };
+
+    let mut response = Response::new(StatusCode::Ok);
+    response.set_body(Body::from_json(&response_body)?);
+    Ok(response)
+}
+
+pub async fn openapi<R>(_req: Request<TicketStore<R>>) -> tide::Result {
+    let mut response = Response::new(StatusCode::Ok);
+    response.set_body(Body::from_json(&ApiDoc::openapi())?);
+    Ok(response)
+}
+
+/// Request counts and latencies, tickets per status and store lock waits, for Prometheus to scrape.
+pub async fn metrics<R: TicketRepository>(req: Request<TicketStore<R>>) -> tide::Result {
+    authorize(&req, Action::Read)?;
+    let store = req.state();
+    let tickets_by_status = store.read().await.count_by_status().await;
+    let mut response = Response::new(StatusCode::Ok);
+    response.set_body(store.metrics().render(&tickets_by_status));
+    response.set_content_type("text/plain; version=0.0.4");
+    Ok(response)
+}
+
+fn etag(ticket: &Ticket) -> String {
+    format!("\"{}\"", ticket.version)
+}
+
+/// The version a write is conditioned on, as given by the `If-Match` header.
+/// Only a single entity tag (or `*`, which matches any version) is supported.
+fn if_match<R>(req: &Request<TicketStore<R>>) -> Result<Option<u64>, MyError> {
+    let Some(header) = req.header("If-Match") else {
+        return Ok(None);
+    };
+    let value = header.as_str().trim();
+    if value == "*" {
+        return Ok(None);
+    }
+    value
+        .strip_prefix('"').and_then(|v| v.strip_suffix('"'))
+        .and_then(|v| v.parse::<u64>().ok())
+        .map(Some)
+        .ok_or(PreconditionFailed(format!("Unknown entity tag {}", value)))
+}
+
+/// The store, recording the user the request was authenticated as, if any, as the author of changes.
+pub(crate) fn store_for<R: TicketRepository>(req: &Request<TicketStore<R>>) -> TicketStore<R> {
+    match req.ext::<User>() {
+        Some(user) => req.state().acting_as(user.name.clone()),
+        None => req.state().clone(),
+    }
+}
+
+/// Checks that the user the request was authenticated as may perform `action`.
+/// Without authentication, every request is allowed.
+pub(crate) fn authorize<R>(req: &Request<TicketStore<R>>, action: Action) -> Result<(), MyError> {
+    authorize_user(req.ext::<User>(), action)
+}
+
+pub(crate) fn authorize_user(user: Option<&User>, action: Action) -> Result<(), MyError> {
+    match user {
+        Some(user) => user.authorize(action).map_err(MyError::Forbidden),
+        None => Ok(()),
+    }
+}
+
+/// Checks that the user may patch tickets in some way, before looking at the patch:
+/// `authorize_patch` then checks what the patch does.
+pub(crate) fn authorize_any_patch(user: Option<&User>) -> Result<(), MyError> {
+    authorize_user(user, Action::ChangeStatus).or_else(|_| authorize_user(user, Action::Patch))
+}
+
+/// Changing the status and editing the other fields may require different roles.
+/// Patches not changing the status, even empty ones, are edits.
+pub(crate) fn authorize_patch(user: Option<&User>, patch: &TicketPatch) -> Result<(), MyError> {
+    if patch.status.is_some() {
+        authorize_user(user, Action::ChangeStatus)?;
+    }
+    if patch.edits_fields() || patch.status.is_none() {
+        authorize_user(user, Action::Patch)?;
+    }
+    Ok(())
+}
+
+fn ticket_id_param<R>(req: &Request<TicketStore<R>>) -> Result<TicketId, MyError> {
     let ticket_id = req
-        .param("id").map_err(|_| BadRequest("Missing id parameter".to_string()))?
-        .parse::<u64>().map_err(|_| BadRequest("Wrong id parameter".to_string()))?;
+        .param("id").map_err(|_| BadRequest { field: "id", message: "Missing id parameter".to_string() })?
+        .parse::<u64>().map_err(|_| BadRequest { field: "id", message: "Wrong id parameter".to_string() })?;
+    Ok(TicketId(ticket_id))
+}
+
+#[utoipa::path(
+    get, path = "/tickets/{id}", params(("id" = u64, Path)),
+    responses(
+        (status = 200, body = GetTicketResponse, headers(("ETag" = String))),
+        (status = 404, body = ErrorResponse),
+    ),
+)]
+pub async fn get_ticket<R: TicketRepository>(req: Request<TicketStore<R>>) -> tide::Result {
+    authorize(&req, Action::Read)?;
+    let ticket_id = ticket_id_param(&req)?;
 
     let store = req.state();
-    let inner_lock = store.read().await.get(TicketId(ticket_id)).ok_or(NotFound("Ticket not found".to_string()))?;
+    let inner_lock = store.read().await.get(ticket_id).ok_or(NotFound(ticket_id))?;
     let ticket_guard = inner_lock.read();
     let ticket = ticket_guard.await;
 
     let response_body = GetTicketResponse(ticket.clone());
 
     let mut response = Response::new(StatusCode::Ok);
+    response.insert_header("ETag", etag(&ticket));
     response.set_body(Body::from_json(&response_body)?);
     Ok(response)
 }
 
+#[utoipa::path(
+    get, path = "/tickets/{id}/history", params(("id" = u64, Path)),
+    responses(
+        (status = 200, body = TicketHistoryResponse, description = "Also available for deleted tickets"),
+        (status = 404, body = ErrorResponse),
+    ),
+)]
+pub async fn ticket_history<R: TicketRepository>(req: Request<TicketStore<R>>) -> tide::Result {
+    authorize(&req, Action::Read)?;
+    let ticket_id = ticket_id_param(&req)?;
+
+    let store = req.state();
+    let entries = store.read().await.history(ticket_id);
+    if entries.is_empty() {
+        return Err(NotFound(ticket_id).into());
+    }
+    let response_body = TicketHistoryResponse { entries };
+
+    let mut response = Response::new(StatusCode::Ok);
+    response.set_body(Body::from_json(&response_body)?);
+    Ok(response)
+}
+
+#[utoipa::path(
+    post, path = "/tickets/{id}/links", request_body = TicketLink,
+    params(("id" = u64, Path)),
+    responses(
+        (status = 204),
+        (status = 400, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 409, body = ErrorResponse, description = "The link would create a cycle, or give a second parent to a ticket"),
+    ),
+)]
+pub async fn link_ticket<R: TicketRepository>(mut req: Request<TicketStore<R>>) -> tide::Result {
+    authorize(&req, Action::Link)?;
+    let ticket_id = ticket_id_param(&req)?;
+    let link: TicketLink = req.body_json()
+        .await.map_err(|e| InvalidJson(e.to_string()))?;
+
+    let store = req.state();
+    store.read().await.link(link.relation.link(ticket_id, link.ticket_id)).map_err(MyError::from)?;
+
+    Ok(Response::new(StatusCode::NoContent))
+}
+
+#[utoipa::path(
+    get, path = "/tickets/{id}/links", params(("id" = u64, Path)),
+    responses(
+        (status = 200, body = ListLinksResponse),
+        (status = 404, body = ErrorResponse),
+    ),
+)]
+pub async fn list_links<R: TicketRepository>(req: Request<TicketStore<R>>) -> tide::Result {
+    authorize(&req, Action::Read)?;
+    let ticket_id = ticket_id_param(&req)?;
+
+    let store = req.state();
+    let links = store.read().await.links(ticket_id).map_err(MyError::from)?;
+    let response_body = ListLinksResponse {
+        links: links.iter().map(|link| TicketLink::new(ticket_id, link)).collect(),
+    };
+
+    let mut response = Response::new(StatusCode::Ok);
+    response.set_body(Body::from_json(&response_body)?);
+    Ok(response)
+}
+
+#[utoipa::path(
+    delete, path = "/tickets/{id}/links", params(("id" = u64, Path), TicketLink),
+    responses(
+        (status = 204, description = "Also when there was no such link"),
+        (status = 400, body = ErrorResponse),
+    ),
+)]
+pub async fn unlink_ticket<R: TicketRepository>(req: Request<TicketStore<R>>) -> tide::Result {
+    authorize(&req, Action::Link)?;
+    let ticket_id = ticket_id_param(&req)?;
+    let link: TicketLink = req.query()
+        .map_err(|e| BadRequest { field: "query", message: e.to_string() })?;
+
+    let store = req.state();
+    store.read().await.unlink(link.relation.link(ticket_id, link.ticket_id)).map_err(MyError::from)?;
+
+    Ok(Response::new(StatusCode::NoContent))
+}
+
+#[utoipa::path(
+    get, path = "/tickets/{id}/blockers", params(("id" = u64, Path)),
+    responses(
+        (status = 200, body = TicketBlockersResponse),
+        (status = 404, body = ErrorResponse),
+    ),
+)]
+pub async fn ticket_blockers<R: TicketRepository>(req: Request<TicketStore<R>>) -> tide::Result {
+    authorize(&req, Action::Read)?;
+    let ticket_id = ticket_id_param(&req)?;
+
+    let store = req.state();
+    let blockers = store.read().await.blockers(ticket_id).map_err(MyError::from)?;
+    let response_body = TicketBlockersResponse { blockers };
+
+    let mut response = Response::new(StatusCode::Ok);
+    response.set_body(Body::from_json(&response_body)?);
+    Ok(response)
+}
+
+#[utoipa::path(
+    post, path = "/tickets/{id}/comments", request_body = CreateCommentRequest,
+    params(("id" = u64, Path)),
+    responses(
+        (status = 200, body = GetCommentResponse),
+        (status = 400, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    ),
+)]
+pub async fn new_comment<R: TicketRepository>(mut req: Request<TicketStore<R>>) -> tide::Result {
+    authorize(&req, Action::Comment)?;
+    let ticket_id = ticket_id_param(&req)?;
+    let comment_request: CreateCommentRequest = req.body_json()
+        .await.map_err(|e| InvalidJson(e.to_string()))?;
+
+    let draft = comment_request.into_draft(req.ext::<User>())?;
+    let store = store_for(&req);
+    let comment = store.read().await.add_comment(ticket_id, draft).map_err(MyError::from)?;
+
+    let mut response = Response::new(StatusCode::Ok);
+    response.set_body(Body::from_json(&GetCommentResponse(comment))?);
+    Ok(response)
+}
+
+#[utoipa::path(
+    get, path = "/tickets/{id}/comments", params(("id" = u64, Path), ListCommentsQuery),
+    responses(
+        (status = 200, body = ListCommentsResponse),
+        (status = 400, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    ),
+)]
+pub async fn list_comments<R: TicketRepository>(req: Request<TicketStore<R>>) -> tide::Result {
+    authorize(&req, Action::Read)?;
+    let ticket_id = ticket_id_param(&req)?;
+    let query: ListCommentsQuery = req.query()
+        .map_err(|e| BadRequest { field: "query", message: e.to_string() })?;
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if limit == 0 || limit > MAX_PAGE_SIZE {
+        let message = format!("Limit must be between 1 and {}", MAX_PAGE_SIZE);
+        return Err(BadRequest { field: "limit", message }.into());
+    }
+
+    let store = req.state();
+    let comments = store.read().await.comments(ticket_id, query.after.map(CommentId), limit)
+        .map_err(MyError::from)?;
+    let next_after = if comments.len() == limit { comments.last().map(|c| c.id) } else { None };
+
+    let response_body = ListCommentsResponse {
+        comments: comments.into_iter().map(GetCommentResponse).collect(),
+        next_after,
+    };
+
+    let mut response = Response::new(StatusCode::Ok);
+    response.set_body(Body::from_json(&response_body)?);
+    Ok(response)
+}
+
+#[utoipa::path(
+    patch, path = "/tickets/{id}", request_body = PatchTicketRequest,
+    params(("id" = u64, Path), ("If-Match" = Option<String>, Header)),
+    responses(
+        (status = 200, body = GetTicketResponse, headers(("ETag" = String))),
+        (status = 400, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 409, body = ErrorResponse),
+        (status = 412, body = ErrorResponse),
+    ),
+)]
+pub async fn patch_ticket<R: TicketRepository>(mut req: Request<TicketStore<R>>) -> tide::Result {
+    authorize_any_patch(req.ext::<User>())?;
+    let ticket_id = ticket_id_param(&req)?;
+    let expected_version = if_match(&req)?;
+    let patch_request: PatchTicketRequest = req.body_json()
+        .await.map_err(|e| InvalidJson(e.to_string()))?;
+
+    let patch = patch_request.try_into().map_err(MyError::from)?;
+    authorize_patch(req.ext::<User>(), &patch)?;
+    let store = store_for(&req);
+    let ticket = store.read().await.patch(ticket_id, patch, expected_version).await
+        .map_err(MyError::from)?;
+
+    let mut response = Response::new(StatusCode::Ok);
+    response.insert_header("ETag", etag(&ticket));
+    let response_body = GetTicketResponse(ticket);
+    response.set_body(Body::from_json(&response_body)?);
+    Ok(response)
+}
+
+#[utoipa::path(
+    delete, path = "/tickets/{id}",
+    params(("id" = u64, Path), ("If-Match" = Option<String>, Header)),
+    responses(
+        (status = 204),
+        (status = 404, body = ErrorResponse),
+        (status = 412, body = ErrorResponse),
+    ),
+)]
+pub async fn delete_ticket<R: TicketRepository>(req: Request<TicketStore<R>>) -> tide::Result {
+    authorize(&req, Action::Delete)?;
+    let ticket_id = ticket_id_param(&req)?;
+    let expected_version = if_match(&req)?;
+
+    let store = store_for(&req);
+    store.write().await.remove_ticket(ticket_id, expected_version).await
+        .map_err(MyError::from)?;
+
+    Ok(Response::new(StatusCode::NoContent))
+}
+
+#[utoipa::path(
+    get, path = "/tickets/events",
+    params(("Last-Event-ID" = Option<u64>, Header, description = "Resume after this event")),
+    responses(
+        (status = 200, content_type = "text/event-stream", body = TicketEventData,
+            description = "`created`, `updated` and `deleted` events, with the event sequence number as id"),
+        (status = 400, body = ErrorResponse),
+        (status = 503, body = ErrorResponse),
+    ),
+)]
+pub async fn ticket_events<R: TicketRepository>(req: Request<TicketStore<R>>) -> tide::Result {
+    authorize(&req, Action::Read)?;
+    let last_seen = match req.header("Last-Event-ID") {
+        Some(value) => Some(value.as_str().trim().parse::<u64>().map_err(|_| BadRequest {
+            field: "Last-Event-ID",
+            message: format!("Invalid event id {:?}", value.as_str()),
+        })?),
+        None => None,
+    };
+    let subscription = req.state().events().subscribe(last_seen)
+        .ok_or_else(|| Unavailable("The server is shutting down".to_string()))?;
+
+    let (sender, receiver) = mpsc::channel(16);
+    tokio::spawn(forward_events(subscription, sender));
+    let mut response = Response::new(StatusCode::Ok);
+    response.insert_header("Cache-Control", "no-cache");
+    response.set_content_type(tide::http::mime::SSE);
+    response.set_body(Body::from_reader(BufReader::new(receiver.into_async_read()), None));
+    Ok(response)
+}
+
+/// How long clients should wait before reconnecting to `GET /tickets/events`.
+const EVENTS_RETRY: Duration = Duration::from_secs(2);
+
+/// Writes the events of `subscription` to `sender`, encoded as Server-Sent Events.
+async fn forward_events(subscription: Subscription, mut sender: mpsc::Sender<std::io::Result<String>>) {
+    // Sent right away, so the response head goes out before the first event.
+    let retry = format!("retry: {}\n\n", EVENTS_RETRY.as_millis());
+    if sender.send(Ok(retry)).await.is_err() {
+        return;
+    }
+    for event in subscription.missed {
+        if sender.send(Ok(encode_event(event))).await.is_err() {
+            return;
+        }
+    }
+    let mut receiver = subscription.receiver;
+    // Ends when the server shuts down, or when this client lags too far behind:
+    // it can then reconnect and resume from the backlog.
+    while let Ok(event) = receiver.recv().await {
+        if sender.send(Ok(encode_event(event))).await.is_err() {
+            // The client went away.
+            return;
+        }
+    }
+}
+
+fn encode_event(event: TicketEvent) -> String {
+    let name = match event.change {
+        TicketChange::Created(_) => "created",
+        TicketChange::Updated(_) => "updated",
+        TicketChange::Deleted(_) => "deleted",
+    };
+    // Serialized JSON has no newlines, so it fits in a single `data` line.
+    let data = serde_json::to_string(&TicketEventData::from(event.change))
+        .expect("ticket events can always be serialized");
+    format!("id: {}\nevent: {}\ndata: {}\n\n", event.sequence, name, data)
+}
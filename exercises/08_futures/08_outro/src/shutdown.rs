@@ -0,0 +1,154 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use async_h1::server::{ConnectionStatus, Server};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio::task::{JoinHandle, JoinSet};
+
+/// Asks a running server to stop. Clones all control the same server.
+#[derive(Clone, Debug)]
+pub struct Shutdown {
+    sender: Arc<watch::Sender<bool>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Shutdown { sender: Arc::new(watch::Sender::new(false)) }
+    }
+
+    pub fn trigger(&self) {
+        self.sender.send_replace(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.sender.borrow()
+    }
+
+    /// Resolves once `trigger` has been called.
+    pub async fn triggered(&self) {
+        let mut receiver = self.sender.subscribe();
+        // The sender lives in `self`, so the channel cannot be closed.
+        let _ = receiver.wait_for(|triggered| *triggered).await;
+    }
+
+    /// Triggers the shutdown when the process receives SIGTERM or SIGINT.
+    pub fn trigger_on_signals(&self) -> JoinHandle<()> {
+        let shutdown = self.clone();
+        tokio::spawn(async move {
+            match termination_signal().await {
+                Ok(()) => tide::log::info!("Received a termination signal, shutting down"),
+                Err(e) => tide::log::error!("Cannot listen for termination signals: {}", e),
+            }
+            shutdown.trigger();
+        })
+    }
+}
+
+#[cfg(unix)]
+async fn termination_signal() -> io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result,
+        _ = terminate.recv() => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+async fn termination_signal() -> io::Result<()> {
+    tokio::signal::ctrl_c().await
+}
+
+/// Serves `app` on `listener` until `shutdown` is triggered.
+///
+/// From then on no connection is accepted, idle keep-alive connections are
+/// closed, and requests being handled get up to `timeout` to complete.
+/// Returns `false` if some of them had to be cut off.
+pub async fn serve<State>(listener: TcpListener, app: tide::Server<State>, shutdown: Shutdown, timeout: Duration) -> bool
+where
+    State: Clone + Send + Sync + 'static,
+{
+    let mut connections = JoinSet::new();
+    loop {
+        tokio::select! {
+            _ = shutdown.triggered() => break,
+            accepted = listener.accept() => match accepted {
+                Ok((stream, peer_addr)) => {
+                    connections.spawn(handle_connection(app.clone(), stream, peer_addr, shutdown.clone()));
+                }
+                Err(e) => {
+                    tide::log::error!("Failed to accept a connection: {}", e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+            },
+            // Reap finished connections, so the set does not grow forever.
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+        }
+    }
+    drop(listener);
+
+    let drained = tokio::time::timeout(timeout, async {
+        while connections.join_next().await.is_some() {}
+    }).await;
+    if drained.is_err() {
+        tide::log::warn!("{} connections still busy after {:?}, closing them", connections.len(), timeout);
+        connections.shutdown().await;
+    }
+    drained.is_ok()
+}
+
+async fn handle_connection<State>(app: tide::Server<State>, stream: tokio::net::TcpStream, peer_addr: SocketAddr, shutdown: Shutdown)
+where
+    State: Clone + Send + Sync + 'static,
+{
+    let local_addr = stream.local_addr().ok();
+    let stream = match stream.into_std() {
+        Ok(stream) => async_std::net::TcpStream::from(stream),
+        Err(e) => {
+            tide::log::error!("Failed to set up a connection: {}", e);
+            return;
+        }
+    };
+    // Set as soon as the head of a request has been read, until its response is written.
+    let busy = AtomicBool::new(false);
+    let mut server = Server::new(stream, |mut req| {
+        busy.store(true, Ordering::SeqCst);
+        req.set_local_addr(local_addr);
+        req.set_peer_addr(Some(peer_addr));
+        let app = app.clone();
+        async move { app.respond(req).await }
+    });
+
+    while !shutdown.is_triggered() {
+        let accept_one = server.accept_one();
+        tokio::pin!(accept_one);
+        let status = tokio::select! {
+            status = &mut accept_one => status,
+            _ = shutdown.triggered() => {
+                if !busy.load(Ordering::SeqCst) {
+                    // Idle, or still waiting for the head of the next request.
+                    break;
+                }
+                accept_one.await
+            }
+        };
+        busy.store(false, Ordering::SeqCst);
+        match status {
+            Ok(ConnectionStatus::KeepAlive) => continue,
+            Ok(ConnectionStatus::Close) => break,
+            Err(e) => {
+                tide::log::error!("async-h1 error: {}", e);
+                break;
+            }
+        }
+    }
+}
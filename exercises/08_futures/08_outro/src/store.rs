@@ -1,72 +1,393 @@
+use std::io;
+use std::sync::{Arc, Mutex};
 use std::collections::BTreeMap;
-use std::sync::Arc;
+use std::time::{Duration, Instant};
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use ticket_fields::TicketAssignee;
 use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
-use crate::data::{Status, Ticket, TicketDraft};
+use tokio::task::JoinHandle;
+use utoipa::ToSchema;
+use crate::data::{self, Comment, CommentDraft, Status, Ticket, TicketDraft, TicketFilter, TicketPatch};
+use crate::events::{EventBus, TicketChange};
+use crate::history::HistoryEntry;
+use crate::links::{Link, LinkError};
+use crate::metrics::{LockMode, Metrics};
+use crate::repository::{InMemoryRepository, TicketRepository};
+use crate::search::{self, SearchIndex};
+use crate::workflow::{IllegalTransition, Workflow};
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
 pub struct TicketId(pub u64);
 
 impl From<u64> for TicketId {
     fn from(id: u64) -> Self { TicketId(id) }
 }
 
-#[derive(Clone)]
-pub struct TicketStore {
-    lock: Arc<RwLock<TicketStoreInternal>>,
+/// Identifies a comment among the ones of its ticket.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+pub struct CommentId(pub u64);
+
+#[derive(Error, Debug)]
+pub enum StoreError {
+    #[error("Ticket {} not found", .0.0)]
+    NotFound(TicketId),
+
+    #[error("Ticket is at version {current}, not {expected}")]
+    VersionMismatch { expected: u64, current: u64 },
+
+    #[error(transparent)]
+    IllegalTransition(#[from] IllegalTransition),
+
+    /// Never `LinkError::Io`, which becomes `StoreError::Io`.
+    #[error(transparent)]
+    InvalidLink(LinkError),
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
 }
 
-pub struct TicketStoreInternal {
-    tickets: BTreeMap<TicketId, Arc<RwLock<Ticket>>>,
-    counter: u64,
+impl From<LinkError> for StoreError {
+    fn from(error: LinkError) -> Self {
+        match error {
+            LinkError::Io(e) => StoreError::Io(e),
+            e => StoreError::InvalidLink(e),
+        }
+    }
+}
+
+fn check_version(ticket: &Ticket, expected_version: Option<u64>) -> Result<(), StoreError> {
+    match expected_version {
+        Some(expected) if expected != ticket.version => {
+            Err(StoreError::VersionMismatch { expected, current: ticket.version })
+        }
+        _ => Ok(()),
+    }
+}
+
+fn new_ticket(id: TicketId, draft: TicketDraft, status: Status) -> Ticket {
+    Ticket {
+        id,
+        title: draft.title,
+        description: draft.description,
+        status,
+        priority: draft.priority,
+        assignee: draft.assignee,
+        labels: draft.labels,
+        version: 1,
+    }
+}
+
+pub struct TicketStore<R = InMemoryRepository> {
+    lock: Arc<RwLock<TicketStoreInternal<R>>>,
+    events: Arc<EventBus>,
+    workflow: Arc<Workflow>,
+    metrics: Arc<Metrics>,
+    /// Recorded in the history of the tickets changed through this handle.
+    actor: Option<TicketAssignee>,
+}
+
+// Derived `Clone` would needlessly require `R: Clone`.
+impl<R> Clone for TicketStore<R> {
+    fn clone(&self) -> Self {
+        Self {
+            lock: self.lock.clone(),
+            events: self.events.clone(),
+            workflow: self.workflow.clone(),
+            metrics: self.metrics.clone(),
+            actor: self.actor.clone(),
+        }
+    }
 }
 
-pub struct TicketStoreReader<'a> {
-    store: RwLockReadGuard<'a, TicketStoreInternal>,
+pub struct TicketStoreInternal<R> {
+    tickets: R,
+    /// Patches update it while holding the lock of the ticket they change.
+    index: Mutex<SearchIndex>,
 }
 
-pub struct TicketStoreWriter<'a> {
-    store: RwLockWriteGuard<'a, TicketStoreInternal>,
+pub struct TicketStoreReader<'a, R> {
+    store: RwLockReadGuard<'a, TicketStoreInternal<R>>,
+    events: &'a EventBus,
+    workflow: &'a Workflow,
+    actor: Option<&'a TicketAssignee>,
 }
 
-impl TicketStoreReader<'_> {
+pub struct TicketStoreWriter<'a, R> {
+    store: RwLockWriteGuard<'a, TicketStoreInternal<R>>,
+    events: &'a EventBus,
+    actor: Option<&'a TicketAssignee>,
+}
+
+impl<R: TicketRepository> TicketStoreReader<'_, R> {
     pub fn get(&self, id: TicketId) -> Option<Arc<RwLock<Ticket>>> {
-        self.store.tickets.get(&id).cloned()
+        self.store.tickets.get(id)
+    }
+
+    /// Returns up to `limit` tickets with an id greater than `after`, in id order,
+    /// keeping only the ones matching `filter`.
+    pub async fn list(&self, after: Option<TicketId>, filter: &TicketFilter, limit: usize) -> Vec<Ticket> {
+        let mut result = Vec::new();
+        for ticket_lock in self.store.tickets.scan(after) {
+            if result.len() >= limit {
+                break;
+            }
+            let ticket = ticket_lock.read().await;
+            if filter.matches(&ticket) {
+                result.push(ticket.clone());
+            }
+        }
+        result
+    }
+
+    /// How many tickets are in each status, including the ones no ticket is in.
+    pub async fn count_by_status(&self) -> BTreeMap<Status, usize> {
+        let mut counts: BTreeMap<_, _> = Status::ALL.into_iter().map(|status| (status, 0)).collect();
+        for ticket_lock in self.store.tickets.scan(None) {
+            *counts.entry(ticket_lock.read().await.status).or_default() += 1;
+        }
+        counts
+    }
+
+    /// Holding the store read lock, so the ticket can't be deleted meanwhile
+    /// and leave the comment behind.
+    pub fn add_comment(&self, ticket_id: TicketId, draft: CommentDraft) -> Result<Comment, StoreError> {
+        self.get(ticket_id).ok_or(StoreError::NotFound(ticket_id))?;
+        Ok(self.store.tickets.add_comment(ticket_id, draft, data::now())?)
+    }
+
+    /// Returns up to `limit` comments of a ticket with an id greater than `after`, oldest first.
+    pub fn comments(&self, ticket_id: TicketId, after: Option<CommentId>, limit: usize) -> Result<Vec<Comment>, StoreError> {
+        self.get(ticket_id).ok_or(StoreError::NotFound(ticket_id))?;
+        Ok(self.store.tickets.comments(ticket_id, after, limit))
+    }
+
+    /// Links two existing tickets. Returns whether the link is new.
+    /// See `LinkGraph` for the links that are rejected.
+    pub fn link(&self, link: Link) -> Result<bool, StoreError> {
+        for id in [link.from, link.to] {
+            self.get(id).ok_or(StoreError::NotFound(id))?;
+        }
+        Ok(self.store.tickets.add_link(link)?)
+    }
+
+    /// Returns whether the link was there.
+    pub fn unlink(&self, link: Link) -> Result<bool, StoreError> {
+        Ok(self.store.tickets.remove_link(link)?)
+    }
+
+    /// The links a ticket is the source or the target of.
+    pub fn links(&self, id: TicketId) -> Result<Vec<Link>, StoreError> {
+        self.get(id).ok_or(StoreError::NotFound(id))?;
+        Ok(self.store.tickets.links_of(id))
+    }
+
+    /// The tickets blocking a ticket, directly or not, closest first.
+    pub fn blockers(&self, id: TicketId) -> Result<Vec<TicketId>, StoreError> {
+        self.get(id).ok_or(StoreError::NotFound(id))?;
+        Ok(self.store.tickets.blockers(id))
+    }
+
+    /// Every change applied to the ticket, oldest first, including its deletion.
+    /// Empty if it never existed.
+    pub fn history(&self, id: TicketId) -> Vec<HistoryEntry> {
+        self.store.tickets.history(id)
+    }
+
+    /// Returns up to `limit` tickets matching `query`, best matches first, with their score.
+    /// See `SearchIndex::search` for how they are matched.
+    pub async fn search(&self, query: &str, limit: usize) -> Vec<(Ticket, f64)> {
+        let ranked = self.store.index.lock().unwrap().search(query, limit);
+        let mut hits = Vec::with_capacity(ranked.len());
+        for (id, score) in ranked {
+            let Some(ticket_lock) = self.get(id) else { continue };
+            let ticket = ticket_lock.read().await.clone();
+            // It may have been patched since the index was queried.
+            if search::matches(query, &ticket) {
+                hits.push((ticket, score));
+            }
+        }
+        hits
+    }
+
+    /// Applies `patch` while holding the write lock of that single ticket,
+    /// so concurrent patches to other tickets are not blocked.
+    /// If `expected_version` is given, the ticket must still be at that version.
+    /// Status changes must be allowed by the workflow of the store.
+    ///
+    /// Like every mutation, it is recorded in the history of the ticket along with the change.
+    pub async fn patch(&self, id: TicketId, patch: TicketPatch, expected_version: Option<u64>) -> Result<Ticket, StoreError> {
+        let ticket_lock = self.get(id).ok_or(StoreError::NotFound(id))?;
+        let mut ticket = ticket_lock.write().await;
+        check_version(&ticket, expected_version)?;
+        let mut patched = ticket.clone();
+        patched.apply(patch, self.workflow)?;
+        if patched == *ticket {
+            return Ok(patched);
+        }
+        let entry = HistoryEntry::updated(&ticket, &patched).by(self.actor);
+        self.store.tickets.update(&patched, entry)?;
+        *ticket = patched.clone();
+        self.store.index.lock().unwrap().insert(&patched);
+        // Still holding the ticket lock, so the events of a ticket are in order.
+        self.events.publish(TicketChange::Updated(patched.clone()));
+        Ok(patched)
     }
 }
 
-impl TicketStoreWriter<'_> {
-    pub fn add_ticket(&mut self, ticket: TicketDraft) -> TicketId {
-        let id = TicketId(self.store.counter);
-        self.store.counter += 1;
-        let ticket = Ticket {
-            id,
-            title: ticket.title,
-            description: ticket.description,
-            status: Status::ToDo,
-        };
-        let ticket = Arc::new(RwLock::new(ticket));
-        self.store.tickets.insert(id, ticket);
-        id
+impl<R: TicketRepository> TicketStoreWriter<'_, R> {
+    pub fn add_ticket(&mut self, ticket: TicketDraft) -> io::Result<TicketId> {
+        self.insert(ticket, Status::ToDo)
+    }
+
+    /// Adds tickets that may already have moved past the first status, regardless
+    /// of the workflow. They are stored with a single write: either all of them
+    /// are added, or none of them and the error is returned.
+    pub fn import(&mut self, tickets: Vec<(TicketDraft, Status)>) -> io::Result<Vec<TicketId>> {
+        if tickets.is_empty() {
+            return Ok(Vec::new());
+        }
+        let first = self.store.tickets.next_id().0;
+        let tickets: Vec<_> = tickets.into_iter().zip(first..)
+            .map(|((draft, status), id)| new_ticket(TicketId(id), draft, status))
+            .collect();
+        let ids = tickets.iter().map(|ticket| ticket.id).collect();
+        let entries = tickets.iter().map(|ticket| HistoryEntry::created(ticket).by(self.actor)).collect();
+        self.store.tickets.insert_all(tickets.clone(), entries)?;
+        let index = self.store.index.get_mut().unwrap();
+        for ticket in tickets {
+            index.insert(&ticket);
+            self.events.publish(TicketChange::Created(ticket));
+        }
+        Ok(ids)
+    }
+
+    fn insert(&mut self, draft: TicketDraft, status: Status) -> io::Result<TicketId> {
+        let ticket = new_ticket(self.store.tickets.next_id(), draft, status);
+        let id = ticket.id;
+        let entry = HistoryEntry::created(&ticket).by(self.actor);
+        self.store.tickets.insert(ticket.clone(), entry)?;
+        self.store.index.get_mut().unwrap().insert(&ticket);
+        self.events.publish(TicketChange::Created(ticket));
+        Ok(id)
+    }
+
+    /// Removes the ticket, its comments and its links from the store. Ids of removed tickets are
+    /// never handed out again.
+    ///
+    /// Patches are applied while holding the store read lock, so they either
+    /// complete before the removal or observe the ticket as missing.
+    /// Readers that already cloned the `Arc` keep a consistent snapshot of
+    /// the ticket as it was right before it got removed.
+    pub async fn remove_ticket(&mut self, id: TicketId, expected_version: Option<u64>) -> Result<Arc<RwLock<Ticket>>, StoreError> {
+        let ticket_lock = self.store.tickets.get(id).ok_or(StoreError::NotFound(id))?;
+        let ticket = ticket_lock.read().await.clone();
+        check_version(&ticket, expected_version)?;
+        let entry = HistoryEntry::deleted(&ticket).by(self.actor);
+        let removed = self.store.tickets.delete(id, entry)?.ok_or(StoreError::NotFound(id))?;
+        self.store.index.get_mut().unwrap().remove(id);
+        self.events.publish(TicketChange::Deleted(id));
+        Ok(removed)
+    }
+
+    /// Compacts the storage into a snapshot of the current content.
+    /// No patch can be in flight, since they all hold the store read lock.
+    pub async fn snapshot(&mut self) -> io::Result<()> {
+        let mut tickets = Vec::new();
+        for ticket_lock in self.store.tickets.scan(None) {
+            tickets.push(ticket_lock.read().await.clone());
+        }
+        self.store.tickets.snapshot(tickets)
+    }
+}
+
+impl Default for TicketStore {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl TicketStore {
     pub fn new() -> Self {
-        let internal = TicketStoreInternal {
-            tickets: BTreeMap::new(),
-            counter: 0,
-        };
+        Self::with_repository(InMemoryRepository::new())
+    }
+}
+
+impl<R: TicketRepository> TicketStore<R> {
+    pub fn with_repository(tickets: R) -> Self {
+        let mut index = SearchIndex::new();
+        for ticket_lock in tickets.scan(None) {
+            let ticket = ticket_lock.try_read().expect("tickets are not shared yet");
+            index.insert(&ticket);
+        }
+        let internal = TicketStoreInternal { tickets, index: Mutex::new(index) };
 
         Self {
             lock: Arc::new(RwLock::new(internal)),
+            events: Arc::new(EventBus::default()),
+            workflow: Arc::new(Workflow::default()),
+            metrics: Arc::default(),
+            actor: None,
         }
     }
-    pub async fn read(&self) -> TicketStoreReader {
-        TicketStoreReader { store: self.lock.read().await }
+
+    /// Replaces the default workflow. Meant to be called right after construction:
+    /// clones made before keep the previous workflow.
+    pub fn with_workflow(mut self, workflow: Workflow) -> Self {
+        self.workflow = Arc::new(workflow);
+        self
+    }
+
+    /// A handle on the same store, recording `actor` as the author of the changes made through it.
+    pub fn acting_as(&self, actor: TicketAssignee) -> Self {
+        Self { actor: Some(actor), ..self.clone() }
+    }
+
+    pub fn workflow(&self) -> &Workflow {
+        &self.workflow
+    }
+
+    /// Every change applied through this store is published here.
+    pub fn events(&self) -> &EventBus {
+        &self.events
+    }
+
+    /// Where lock waits are recorded, along with whatever the server records.
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+
+    /// Periodically compacts the storage, until the returned task is aborted.
+    pub fn spawn_snapshots(&self, period: Duration) -> JoinHandle<()> {
+        let store = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if let Err(e) = store.write().await.snapshot().await {
+                    eprintln!("Failed to snapshot the ticket store: {}", e);
+                }
+            }
+        })
+    }
+
+    pub async fn read(&self) -> TicketStoreReader<'_, R> {
+        let start = Instant::now();
+        let store = self.lock.read().await;
+        self.metrics.observe_lock_wait(LockMode::Read, start.elapsed());
+        TicketStoreReader {
+            store,
+            events: &self.events,
+            workflow: &self.workflow,
+            actor: self.actor.as_ref(),
+        }
     }
 
-    pub async fn write(&self) -> TicketStoreWriter {
-        TicketStoreWriter { store: self.lock.write().await }
+    pub async fn write(&self) -> TicketStoreWriter<'_, R> {
+        let start = Instant::now();
+        let store = self.lock.write().await;
+        self.metrics.observe_lock_wait(LockMode::Write, start.elapsed());
+        TicketStoreWriter { store, events: &self.events, actor: self.actor.as_ref() }
     }
 }
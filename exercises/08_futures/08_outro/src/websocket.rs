@@ -0,0 +1,243 @@
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use async_tungstenite::tungstenite::handshake::derive_accept_key;
+use async_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use async_tungstenite::tungstenite::protocol::{CloseFrame, Role};
+use async_tungstenite::tungstenite::Message;
+use async_tungstenite::WebSocketStream;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tide::http::upgrade::Connection;
+use tide::{Request, Response, StatusCode};
+use tokio::sync::broadcast::error::RecvError;
+use crate::auth::User;
+use crate::data::Ticket;
+use crate::events::{TicketChange, TicketEvent};
+use crate::policy::Action;
+use crate::repository::TicketRepository;
+use crate::server::{authorize, authorize_any_patch, authorize_patch, store_for, ErrorBody, ErrorResponse, MyError, PatchTicketRequest, TicketSerializer};
+use crate::store::{TicketId, TicketStore};
+
+/// A command sent by clients of `GET /tickets/ws`, as a JSON text message.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Subscribe { ticket_id: TicketId },
+    Unsubscribe { ticket_id: TicketId },
+    /// Same as `PATCH /tickets/:id`, with `version` in place of `If-Match`.
+    Patch {
+        ticket_id: TicketId,
+        patch: PatchTicketRequest,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        version: Option<u64>,
+    },
+}
+
+/// A message sent by the server on `GET /tickets/ws`, as a JSON text message.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// A subscribed ticket: sent on subscription, then every time it changes.
+    Ticket {
+        #[serde(with = "TicketSerializer")]
+        ticket: Ticket,
+    },
+    /// A subscribed ticket got deleted, which ends the subscription.
+    Deleted { ticket_id: TicketId },
+    /// The outcome of a `patch` command.
+    Patched {
+        #[serde(with = "TicketSerializer")]
+        ticket: Ticket,
+    },
+    Error {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ticket_id: Option<TicketId>,
+        error: ErrorBody,
+    },
+}
+
+impl ServerMessage {
+    fn error(ticket_id: Option<TicketId>, error: MyError) -> Self {
+        ServerMessage::Error { ticket_id, error: ErrorResponse::from(&error).error }
+    }
+}
+
+#[utoipa::path(
+    get, path = "/tickets/ws",
+    params(
+        ("Upgrade" = String, Header, description = "`websocket`"),
+        ("Sec-WebSocket-Key" = String, Header),
+        ("Sec-WebSocket-Version" = String, Header, description = "`13`"),
+    ),
+    responses(
+        (status = 101, description = "Subscribe to tickets and patch them: see `ClientMessage` and `ServerMessage`"),
+        (status = 400, body = ErrorResponse),
+    ),
+)]
+pub async fn ticket_subscriptions<R: TicketRepository>(req: Request<TicketStore<R>>) -> tide::Result {
+    authorize(&req, Action::Read)?;
+    let is_websocket = req.header("Upgrade")
+        .is_some_and(|upgrade| upgrade.as_str().eq_ignore_ascii_case("websocket"));
+    let key = match req.header("Sec-WebSocket-Key") {
+        Some(key) if is_websocket => key.as_str().to_string(),
+        _ => return Err(MyError::BadRequest {
+            field: "Upgrade",
+            message: "Expected a WebSocket handshake".to_string(),
+        }.into()),
+    };
+    if req.header("Sec-WebSocket-Version").is_none_or(|version| version.as_str() != "13") {
+        return Err(MyError::BadRequest {
+            field: "Sec-WebSocket-Version",
+            message: "Only version 13 of the WebSocket protocol is supported".to_string(),
+        }.into());
+    }
+
+    let mut response = Response::new(StatusCode::SwitchingProtocols);
+    response.insert_header("Upgrade", "websocket");
+    response.insert_header("Connection", "Upgrade");
+    response.insert_header("Sec-WebSocket-Accept", derive_accept_key(key.as_bytes()));
+    let http_response: &mut tide::http::Response = response.as_mut();
+    let upgrade = http_response.recv_upgrade().await;
+
+    let store = store_for(&req);
+    let user = req.ext::<User>().cloned();
+    tokio::spawn(async move {
+        // Handed over once the response has been written.
+        if let Some(connection) = upgrade.await {
+            let socket = WebSocketStream::from_raw_socket(connection, Role::Server, None).await;
+            serve_subscriptions(socket, store, user).await;
+        }
+    });
+    Ok(response)
+}
+
+/// Last version of each subscribed ticket sent to the client.
+type Subscriptions = BTreeMap<TicketId, u64>;
+
+/// Commands are authorized as `user`, the one the upgrade request was authenticated as.
+async fn serve_subscriptions<R: TicketRepository>(mut socket: WebSocketStream<Connection>, store: TicketStore<R>, user: Option<User>) {
+    // Subscribed before looking at any ticket, so no change can fall in between.
+    let Some(subscription) = store.events().subscribe(None) else {
+        let _ = socket.close(Some(going_away())).await;
+        return;
+    };
+    let mut events = subscription.receiver;
+    let mut subscriptions = Subscriptions::new();
+
+    loop {
+        let replies = tokio::select! {
+            message = socket.next() => match message {
+                Some(Ok(Message::Text(text))) => handle_command(&store, user.as_ref(), &mut subscriptions, &text).await,
+                Some(Ok(Message::Binary(_))) => {
+                    let error = MyError::InvalidJson("Commands must be sent as text messages".to_string());
+                    vec![ServerMessage::error(None, error)]
+                }
+                // Pings are answered by the socket itself.
+                Some(Ok(_)) => continue,
+                Some(Err(_)) | None => break,
+            },
+            event = events.recv() => match event {
+                Ok(event) => handle_event(&mut subscriptions, event),
+                Err(RecvError::Lagged(_)) => resync(&store, &mut subscriptions).await,
+                Err(RecvError::Closed) => {
+                    // The server is shutting down.
+                    let _ = socket.close(Some(going_away())).await;
+                    break;
+                }
+            },
+        };
+        for reply in replies {
+            let text = serde_json::to_string(&reply).expect("server messages can always be serialized");
+            if socket.send(Message::text(text)).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+async fn handle_command<R: TicketRepository>(store: &TicketStore<R>, user: Option<&User>, subscriptions: &mut Subscriptions, text: &str) -> Vec<ServerMessage> {
+    let command = match serde_json::from_str::<ClientMessage>(text) {
+        Ok(command) => command,
+        Err(e) => return vec![ServerMessage::error(None, MyError::InvalidJson(e.to_string()))],
+    };
+    match command {
+        ClientMessage::Subscribe { ticket_id } => {
+            let Some(ticket_lock) = store.read().await.get(ticket_id) else {
+                return vec![ServerMessage::error(Some(ticket_id), MyError::NotFound(ticket_id))];
+            };
+            let ticket = ticket_lock.read().await.clone();
+            subscriptions.insert(ticket_id, ticket.version);
+            vec![ServerMessage::Ticket { ticket }]
+        }
+        ClientMessage::Unsubscribe { ticket_id } => {
+            subscriptions.remove(&ticket_id);
+            vec![]
+        }
+        ClientMessage::Patch { ticket_id, patch, version } => {
+            if let Err(e) = authorize_any_patch(user) {
+                return vec![ServerMessage::error(Some(ticket_id), e)];
+            }
+            let patch = match patch.try_into() {
+                Ok(patch) => patch,
+                Err(e) => return vec![ServerMessage::error(Some(ticket_id), MyError::from(e))],
+            };
+            if let Err(e) = authorize_patch(user, &patch) {
+                return vec![ServerMessage::error(Some(ticket_id), e)];
+            }
+            match store.read().await.patch(ticket_id, patch, version).await {
+                Ok(ticket) => {
+                    // The change is in the reply, no need to send it again as an update.
+                    if let Some(last_sent) = subscriptions.get_mut(&ticket_id) {
+                        *last_sent = (*last_sent).max(ticket.version);
+                    }
+                    vec![ServerMessage::Patched { ticket }]
+                }
+                Err(e) => vec![ServerMessage::error(Some(ticket_id), MyError::from(e))],
+            }
+        }
+    }
+}
+
+fn handle_event(subscriptions: &mut Subscriptions, event: TicketEvent) -> Vec<ServerMessage> {
+    let ticket_id = event.change.ticket_id();
+    let Some(last_sent) = subscriptions.get_mut(&ticket_id) else {
+        return vec![];
+    };
+    match event.change {
+        TicketChange::Updated(ticket) if ticket.version > *last_sent => {
+            *last_sent = ticket.version;
+            vec![ServerMessage::Ticket { ticket }]
+        }
+        TicketChange::Deleted(_) => {
+            subscriptions.remove(&ticket_id);
+            vec![ServerMessage::Deleted { ticket_id }]
+        }
+        _ => vec![],
+    }
+}
+
+/// Catches up after missing some events, by sending the tickets that changed since.
+async fn resync<R: TicketRepository>(store: &TicketStore<R>, subscriptions: &mut Subscriptions) -> Vec<ServerMessage> {
+    let mut replies = Vec::new();
+    let store = store.read().await;
+    subscriptions.retain(|&ticket_id, _| {
+        let exists = store.get(ticket_id).is_some();
+        if !exists {
+            replies.push(ServerMessage::Deleted { ticket_id });
+        }
+        exists
+    });
+    for (&ticket_id, last_sent) in subscriptions.iter_mut() {
+        let ticket_lock = store.get(ticket_id).expect("deleted tickets were just removed");
+        let ticket = ticket_lock.read().await;
+        if ticket.version > *last_sent {
+            *last_sent = ticket.version;
+            replies.push(ServerMessage::Ticket { ticket: ticket.clone() });
+        }
+    }
+    replies
+}
+
+fn going_away() -> CloseFrame {
+    CloseFrame { code: CloseCode::Away, reason: "Server shutting down".into() }
+}
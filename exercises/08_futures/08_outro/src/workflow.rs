@@ -0,0 +1,115 @@
+use std::collections::{BTreeMap, BTreeSet};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use crate::data::Status;
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("A ticket cannot go from {from} to {to}")]
+pub struct IllegalTransition {
+    pub from: Status,
+    pub to: Status,
+}
+
+/// The status changes tickets are allowed to go through.
+///
+/// In configuration files, maps each status to the ones it can move to:
+/// statuses that are not listed cannot be left.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Workflow {
+    transitions: BTreeMap<Status, BTreeSet<Status>>,
+}
+
+impl Default for Workflow {
+    /// Open tickets can move freely, except that blocked ones have to be unblocked
+    /// and reviewed ones have to be approved or sent back. Done tickets can only
+    /// be reopened as in progress.
+    fn default() -> Self {
+        use Status::*;
+        Workflow::new()
+            .allow(ToDo, [InProgress, Blocked, InReview, Done])
+            .allow(InProgress, [ToDo, Blocked, InReview, Done])
+            .allow(Blocked, [ToDo, InProgress])
+            .allow(InReview, [InProgress, Done])
+            .allow(Done, [InProgress])
+    }
+}
+
+impl Workflow {
+    /// A workflow where tickets can't change status at all.
+    pub fn new() -> Self {
+        Workflow { transitions: BTreeMap::new() }
+    }
+
+    /// A workflow allowing any change of status.
+    pub fn unrestricted() -> Self {
+        Status::ALL.into_iter().fold(Workflow::new(), |workflow, from| workflow.allow(from, Status::ALL))
+    }
+
+    /// Allows tickets in status `from` to move to any of `to`.
+    pub fn allow(mut self, from: Status, to: impl IntoIterator<Item = Status>) -> Self {
+        self.transitions.entry(from).or_default().extend(to);
+        self
+    }
+
+    /// Staying in the same status is always allowed.
+    pub fn check(&self, from: Status, to: Status) -> Result<(), IllegalTransition> {
+        let allowed = from == to || self.transitions.get(&from).is_some_and(|targets| targets.contains(&to));
+        if allowed { Ok(()) } else { Err(IllegalTransition { from, to }) }
+    }
+
+    /// The statuses a ticket in status `from` can move to.
+    pub fn next(&self, from: Status) -> impl Iterator<Item = Status> + '_ {
+        self.transitions.get(&from).into_iter().flatten().copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Status::*;
+
+    #[test]
+    fn test_default_transitions() {
+        let workflow = Workflow::default();
+        assert!(workflow.check(ToDo, Done).is_ok());
+        assert!(workflow.check(Blocked, InProgress).is_ok());
+        assert_eq!(workflow.check(Blocked, Done), Err(IllegalTransition { from: Blocked, to: Done }));
+        assert_eq!(workflow.check(Done, ToDo), Err(IllegalTransition { from: Done, to: ToDo }));
+        assert_eq!(workflow.next(InReview).collect::<Vec<_>>(), vec![InProgress, Done]);
+    }
+
+    #[test]
+    fn test_staying_in_the_same_status_is_always_allowed() {
+        let workflow = Workflow::new();
+        for status in Status::ALL {
+            assert!(workflow.check(status, status).is_ok());
+        }
+        assert!(workflow.check(ToDo, InProgress).is_err());
+        assert_eq!(workflow.next(ToDo).count(), 0);
+    }
+
+    #[test]
+    fn test_unrestricted_allows_everything() {
+        let workflow = Workflow::unrestricted();
+        for from in Status::ALL {
+            for to in Status::ALL {
+                assert!(workflow.check(from, to).is_ok());
+            }
+        }
+    }
+
+    #[test]
+    fn test_allow_adds_to_previous_transitions() {
+        let workflow = Workflow::new().allow(ToDo, [InProgress]).allow(ToDo, [Done]);
+        assert_eq!(workflow.next(ToDo).collect::<Vec<_>>(), vec![InProgress, Done]);
+    }
+
+    #[test]
+    fn test_unlisted_statuses_cannot_be_left() {
+        let workflow: Workflow = toml::from_str("ToDo = [\"Done\"]").unwrap();
+        assert_eq!(workflow, Workflow::new().allow(ToDo, [Done]));
+        let err = workflow.check(Done, ToDo).unwrap_err();
+        assert_eq!(err.to_string(), "A ticket cannot go from Done to ToDo");
+    }
+}
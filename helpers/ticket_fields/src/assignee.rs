@@ -0,0 +1,88 @@
+use std::convert::TryFrom;
+
+/// The user name of whoever a ticket is assigned to.
+#[derive(Debug, PartialEq, Clone, Eq, PartialOrd, Ord, Hash)]
+pub struct TicketAssignee(pub String);
+
+impl TicketAssignee {
+    /// The maximum length of an assignee, in bytes.
+    pub const MAX_LENGTH: usize = 64;
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TicketAssigneeError {
+    #[error("The assignee cannot be empty")]
+    Empty,
+    #[error(
+        "The assignee cannot be longer than {} bytes",
+        TicketAssignee::MAX_LENGTH
+    )]
+    TooLong,
+    #[error("The assignee can only contain letters, digits, '.', '-', '_' and '@', not {0:?}")]
+    InvalidCharacter(char),
+}
+
+impl TryFrom<String> for TicketAssignee {
+    type Error = TicketAssigneeError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        validate(&value)?;
+        Ok(Self(value))
+    }
+}
+
+impl TryFrom<&str> for TicketAssignee {
+    type Error = TicketAssigneeError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        validate(value)?;
+        Ok(Self(value.to_string()))
+    }
+}
+
+fn validate(assignee: &str) -> Result<(), TicketAssigneeError> {
+    if assignee.is_empty() {
+        Err(TicketAssigneeError::Empty)
+    } else if assignee.len() > TicketAssignee::MAX_LENGTH {
+        Err(TicketAssigneeError::TooLong)
+    } else if let Some(c) = assignee
+        .chars()
+        .find(|&c| !c.is_alphanumeric() && !".-_@".contains(c))
+    {
+        Err(TicketAssigneeError::InvalidCharacter(c))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_try_from_string() {
+        let assignee = TicketAssignee::try_from("jane.doe@example.com".to_string()).unwrap();
+        assert_eq!(assignee.0, "jane.doe@example.com");
+    }
+
+    #[test]
+    fn test_try_from_empty_string() {
+        let err = TicketAssignee::try_from("").unwrap_err();
+        assert_eq!(err.to_string(), "The assignee cannot be empty");
+    }
+
+    #[test]
+    fn test_try_from_long_string() {
+        let err = TicketAssignee::try_from("a".repeat(65)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The assignee cannot be longer than 64 bytes"
+        );
+    }
+
+    #[test]
+    fn test_try_from_string_with_spaces() {
+        let err = TicketAssignee::try_from("jane doe").unwrap_err();
+        assert!(matches!(err, TicketAssigneeError::InvalidCharacter(' ')));
+    }
+}
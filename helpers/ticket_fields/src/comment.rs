@@ -0,0 +1,81 @@
+use std::convert::TryFrom;
+
+/// The text of a comment left on a ticket.
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct TicketComment(pub String);
+
+impl TicketComment {
+    /// The maximum length of a comment, in bytes.
+    pub const MAX_LENGTH: usize = 2000;
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TicketCommentError {
+    #[error("The comment cannot be empty")]
+    Empty,
+    #[error(
+        "The comment cannot be longer than {} bytes",
+        TicketComment::MAX_LENGTH
+    )]
+    TooLong,
+}
+
+impl TryFrom<String> for TicketComment {
+    type Error = TicketCommentError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        validate(&value)?;
+        Ok(Self(value))
+    }
+}
+
+impl TryFrom<&str> for TicketComment {
+    type Error = TicketCommentError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        validate(value)?;
+        Ok(Self(value.to_string()))
+    }
+}
+
+fn validate(comment: &str) -> Result<(), TicketCommentError> {
+    if comment.is_empty() {
+        Err(TicketCommentError::Empty)
+    } else if comment.len() > TicketComment::MAX_LENGTH {
+        Err(TicketCommentError::TooLong)
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_try_from_string() {
+        let comment = TicketComment::try_from("Looks good to me".to_string()).unwrap();
+        assert_eq!(comment.0, "Looks good to me");
+    }
+
+    #[test]
+    fn test_try_from_empty_string() {
+        let err = TicketComment::try_from("").unwrap_err();
+        assert_eq!(err.to_string(), "The comment cannot be empty");
+    }
+
+    #[test]
+    fn test_try_from_long_string() {
+        let err = TicketComment::try_from("a".repeat(2001)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The comment cannot be longer than 2000 bytes"
+        );
+    }
+
+    #[test]
+    fn test_length_is_measured_in_bytes() {
+        assert!(TicketComment::try_from("é".repeat(1000)).is_ok());
+        assert!(TicketComment::try_from("é".repeat(1001)).is_err());
+    }
+}
@@ -1,6 +1,11 @@
 #[derive(Debug, PartialEq, Clone, Eq)]
 pub struct TicketDescription(pub String);
 
+impl TicketDescription {
+    /// The maximum length of a description, in bytes.
+    pub const MAX_LENGTH: usize = 500;
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum TicketDescriptionError {
     #[error("The description cannot be empty")]
@@ -30,7 +35,7 @@ impl TryFrom<&str> for TicketDescription {
 fn validate(description: &str) -> Result<(), TicketDescriptionError> {
     if description.is_empty() {
         Err(TicketDescriptionError::Empty)
-    } else if description.len() > 500 {
+    } else if description.len() > TicketDescription::MAX_LENGTH {
         Err(TicketDescriptionError::TooLong)
     } else {
         Ok(())
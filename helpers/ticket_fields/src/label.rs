@@ -0,0 +1,82 @@
+use std::convert::TryFrom;
+
+/// A tag used to group tickets, e.g. `bug` or `area/api`.
+#[derive(Debug, PartialEq, Clone, Eq, PartialOrd, Ord, Hash)]
+pub struct TicketLabel(pub String);
+
+impl TicketLabel {
+    /// The maximum length of a label, in bytes.
+    pub const MAX_LENGTH: usize = 32;
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TicketLabelError {
+    #[error("A label cannot be empty")]
+    Empty,
+    #[error("A label cannot be longer than {} bytes", TicketLabel::MAX_LENGTH)]
+    TooLong,
+    #[error(
+        "A label can only contain lowercase letters, digits, '-', '_', ':' and '/', not {0:?}"
+    )]
+    InvalidCharacter(char),
+}
+
+impl TryFrom<String> for TicketLabel {
+    type Error = TicketLabelError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        validate(&value)?;
+        Ok(Self(value))
+    }
+}
+
+impl TryFrom<&str> for TicketLabel {
+    type Error = TicketLabelError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        validate(value)?;
+        Ok(Self(value.to_string()))
+    }
+}
+
+fn validate(label: &str) -> Result<(), TicketLabelError> {
+    let allowed = |c: char| c.is_lowercase() || c.is_numeric() || "-_:/".contains(c);
+    if label.is_empty() {
+        Err(TicketLabelError::Empty)
+    } else if label.len() > TicketLabel::MAX_LENGTH {
+        Err(TicketLabelError::TooLong)
+    } else if let Some(c) = label.chars().find(|&c| !allowed(c)) {
+        Err(TicketLabelError::InvalidCharacter(c))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_try_from_string() {
+        let label = TicketLabel::try_from("area/api".to_string()).unwrap();
+        assert_eq!(label.0, "area/api");
+    }
+
+    #[test]
+    fn test_try_from_empty_string() {
+        let err = TicketLabel::try_from("").unwrap_err();
+        assert_eq!(err.to_string(), "A label cannot be empty");
+    }
+
+    #[test]
+    fn test_try_from_long_string() {
+        let err = TicketLabel::try_from("a".repeat(33)).unwrap_err();
+        assert_eq!(err.to_string(), "A label cannot be longer than 32 bytes");
+    }
+
+    #[test]
+    fn test_try_from_uppercase_string() {
+        let err = TicketLabel::try_from("Bug").unwrap_err();
+        assert!(matches!(err, TicketLabelError::InvalidCharacter('B')));
+    }
+}
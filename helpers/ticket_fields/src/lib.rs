@@ -1,6 +1,12 @@
+mod assignee;
+mod comment;
 mod description;
+mod label;
 pub mod test_helpers;
 mod title;
 
-pub use description::TicketDescription;
-pub use title::TicketTitle;
+pub use assignee::{TicketAssignee, TicketAssigneeError};
+pub use comment::{TicketComment, TicketCommentError};
+pub use description::{TicketDescription, TicketDescriptionError};
+pub use label::{TicketLabel, TicketLabelError};
+pub use title::{TicketTitle, TicketTitleError};
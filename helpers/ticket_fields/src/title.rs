@@ -3,6 +3,11 @@ use std::convert::TryFrom;
 #[derive(Debug, PartialEq, Clone, Eq)]
 pub struct TicketTitle(pub String);
 
+impl TicketTitle {
+    /// The maximum length of a title, in bytes.
+    pub const MAX_LENGTH: usize = 50;
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum TicketTitleError {
     #[error("The title cannot be empty")]
@@ -32,7 +37,7 @@ impl TryFrom<&str> for TicketTitle {
 fn validate(title: &str) -> Result<(), TicketTitleError> {
     if title.is_empty() {
         Err(TicketTitleError::Empty)
-    } else if title.len() > 50 {
+    } else if title.len() > TicketTitle::MAX_LENGTH {
         Err(TicketTitleError::TooLong)
     } else {
         Ok(())